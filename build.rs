@@ -0,0 +1,4 @@
+fn main() {
+    // `sqlx::migrate!` embeds `migrations/`: rebuild when it changes
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,7 @@
+-- Raw tokens cannot be turned into digests after the fact:
+-- pending subscribers can ask for a fresh link by subscribing again.
+DELETE FROM subscription_tokens;
+ALTER TABLE subscription_tokens RENAME COLUMN subscription_token TO subscription_token_hash;
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL;
+ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NOT NULL;
+CREATE INDEX subscription_tokens_expires_at_idx ON subscription_tokens (expires_at);
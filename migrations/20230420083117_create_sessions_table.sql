@@ -0,0 +1,9 @@
+-- Server-side state of the admin panel sessions, see `PgSessionStore`
+CREATE TABLE sessions(
+    session_key TEXT NOT NULL,
+    -- JSON-encoded map of session keys to JSON-encoded values
+    state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (session_key)
+);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
@@ -0,0 +1,2 @@
+-- Where password reset links are sent. Users without one cannot reset their password.
+ALTER TABLE users ADD COLUMN email TEXT NULL UNIQUE;
@@ -0,0 +1,9 @@
+CREATE TABLE password_reset_tokens(
+    -- SHA3-256 digest of the token sent by email
+    password_reset_token_hash TEXT NOT NULL,
+    user_id uuid NOT NULL
+        REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (password_reset_token_hash)
+);
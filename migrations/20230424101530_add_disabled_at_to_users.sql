@@ -0,0 +1,2 @@
+-- Disabled users keep their row (and history) but can no longer log in
+ALTER TABLE users ADD COLUMN disabled_at timestamptz NULL;
@@ -0,0 +1,2 @@
+-- The request that published the issue, to trace a delivery back to it
+ALTER TABLE issue_delivery_queue ADD COLUMN request_id TEXT NULL;
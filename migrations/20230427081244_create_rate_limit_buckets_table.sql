@@ -0,0 +1,10 @@
+-- Token buckets of the rate limiter, shared by every instance
+CREATE TABLE rate_limit_buckets(
+    key TEXT NOT NULL,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at timestamptz NOT NULL,
+    -- When the bucket will be full again: from then on, the row can be deleted
+    full_at timestamptz NOT NULL,
+    PRIMARY KEY (key)
+);
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
@@ -0,0 +1,8 @@
+-- Issues can be scheduled for later: they are only published once dispatched
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+-- The request that created the issue, stamped on its emails when dispatched
+ALTER TABLE newsletter_issues ADD COLUMN request_id TEXT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+CREATE INDEX newsletter_issues_scheduled_send_at
+    ON newsletter_issues (send_at) WHERE status = 'scheduled';
@@ -0,0 +1,20 @@
+CREATE TABLE newsletter_drafts (
+    draft_id uuid NOT NULL,
+    current_version INT NOT NULL,
+    created_at timestamptz NOT NULL,
+    -- Set once the draft is published: it can no longer be edited
+    newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    PRIMARY KEY(draft_id)
+);
+-- Every edit is kept
+CREATE TABLE newsletter_draft_versions (
+    draft_id uuid NOT NULL REFERENCES newsletter_drafts (draft_id),
+    version INT NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    -- The Markdown the version was derived from, so that it can be edited again
+    markdown_content TEXT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY(draft_id, version)
+);
@@ -0,0 +1,15 @@
+-- One row per issue and recipient, kept after the task leaves `issue_delivery_queue`
+CREATE TABLE newsletter_deliveries (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    -- `bounced` is for recipients the provider reports as undeliverable,
+    -- `skipped` for those who unsubscribed before their email was sent
+    state TEXT NOT NULL CHECK (state IN ('queued', 'sent', 'failed', 'bounced', 'skipped')),
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    -- Postmark's `MessageID`
+    provider_message_id TEXT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,191 @@
+use crate::domain::NewPassword;
+use crate::routes::error_chain_fmt;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use sqlx::{PgExecutor, PgPool};
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    // let row: Option<_> = sqlx::query!(
+    //     r#"
+    //     SELECT user_id, password_hash
+    //     FROM users
+    //     WHERE username = $1
+    //     "#,
+    //     credentials.username,
+    // )
+    // .fetch_optional(pool)
+    // .await
+    // .context("Failed to perform a query to retrieve stored credentials.")
+    // .map_err(PublishError::UnexpectedError)?;
+
+    let mut user_id = None;
+    let mut expected_password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
+    gZiV/M1gPc22ElAH/Jh1Hw$\
+    CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+        .to_string();
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    // let (user_id, expected_password_hash) = get_stored_credentials(&credentials.username, pool)
+    //     .await
+    //     .map_err(PublishError::UnexpectedError)?
+    //     .ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username.")))?;
+
+    // This executes before spawning the new thread
+    // let current_span = tracing::Span::current();
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+        // verify_password_hash(expected_password_hash, credentials.password)
+    })
+    // actix_web::rt::task::spawn_blocking(move || {
+    //     // We then pass ownership to it into the closure
+    //     // and explicitly executes all our computation
+    //     // within its scope.
+    //     current_span.in_scope(|| {
+    //         verify_password_hash(expected_password_hash, credentials.password)
+    //     })
+    // })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    // let expected_password_hash = PasswordHash::new(&expected_password_hash)
+    //     .context("Failed to parse hash in PHC string format.")
+    //     .map_err(PublishError::UnexpectedError)?;
+    //
+    // tracing::info_span!("Verify password hash")
+    //     .in_scope(|| {
+    //         Argon2::default()
+    //             .verify_password(
+    //                 credentials.password.as_bytes(),
+    //                 &expected_password_hash
+    //             ) })
+    //     .context("Invalid password.")
+    //     .map_err(PublishError::AuthError)?;
+
+    // Argon2::default()
+    //     .verify_password(credentials.password.as_bytes(), &expected_password_hash)
+    //     .context("Invalid password.")
+    //     .map_err(PublishError::AuthError)?;
+
+    // This is only set to `Some` if we found credentials in the store
+    // So, even if the default password ends up matching (somehow)
+    // with the provided password,
+    // we never authenticate a non-existing user.
+    // You can easily add a unit test for that precise scenario.
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username."))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+// We extracted the db-querying logic in its own function with its own span.
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1 AND disabled_at IS NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, row.password_hash));
+
+    Ok(row)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: String,
+    password_candidate: String,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(&expected_password_hash)
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+/// Takes a transaction as well as a pool: a password reset changes the
+/// password and consumes its token atomically.
+#[tracing::instrument(name = "Change password", skip(password, executor))]
+pub async fn change_password<'c, E>(
+    user_id: uuid::Uuid,
+    password: NewPassword,
+    executor: E,
+) -> Result<(), anyhow::Error>
+where
+    E: PgExecutor<'c>,
+{
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash,
+        user_id
+    )
+    .execute(executor)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+/// Hash with the same Argon2 parameters `validate_credentials` falls back to,
+/// so that unknown and known usernames take the same time to reject.
+pub fn compute_password_hash(password: NewPassword) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.as_ref().as_bytes(), &salt)?
+    .to_string();
+    Ok(password_hash)
+}
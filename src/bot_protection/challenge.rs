@@ -0,0 +1,22 @@
+/// A third-party challenge (a CAPTCHA, or the likes of Turnstile) that
+/// the subscription form can ask the client to solve.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Whether `response`, as submitted with the form, solves the challenge.
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Stands in for a real provider locally and in tests: only
+/// `FakeChallengeVerifier::PASSING_RESPONSE` solves the challenge.
+pub struct FakeChallengeVerifier;
+
+impl FakeChallengeVerifier {
+    pub const PASSING_RESPONSE: &'static str = "i-am-not-a-robot";
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for FakeChallengeVerifier {
+    async fn verify(&self, response: &str) -> Result<bool, anyhow::Error> {
+        Ok(response == Self::PASSING_RESPONSE)
+    }
+}
@@ -0,0 +1,267 @@
+mod challenge;
+
+pub use challenge::{ChallengeVerifier, FakeChallengeVerifier};
+
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// Checks that a subscription form was filled in by a human, before
+/// anything is written to the database or any email is sent.
+pub struct BotProtection {
+    // Humans need at least this long to fill in the form
+    pub min_submit_time: Duration,
+    // How long a form token can be used after it was issued
+    pub form_token_expiration: Duration,
+    // `None` unless a challenge is configured
+    pub challenge_verifier: Option<Arc<dyn ChallengeVerifier>>,
+}
+
+/// What the client sent along with the subscription form.
+pub struct Submission<'a> {
+    // The honeypot: the field is hidden from humans, so it must be empty
+    pub website: &'a str,
+    pub form_token: Option<&'a str>,
+    pub challenge_response: Option<&'a str>,
+}
+
+#[derive(thiserror::Error)]
+pub enum BotCheckError {
+    #[error("The form was not filled in by a human.")]
+    HoneypotFilled,
+    #[error("The form token is missing: fetch one from `GET /subscriptions/form-token`.")]
+    MissingFormToken,
+    #[error("The form token is invalid.")]
+    InvalidFormToken,
+    #[error("The form token has expired: reload the form.")]
+    ExpiredFormToken,
+    #[error("The form was submitted too quickly.")]
+    SubmittedTooQuickly,
+    #[error("The challenge was not solved.")]
+    ChallengeFailed,
+    #[error("Failed to verify the challenge response.")]
+    VerifierUnavailable(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for BotCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl BotProtection {
+    /// A token recording when the form was served: `<unix timestamp>.<signature>`.
+    pub fn issue_form_token(&self, hmac_secret: &HmacSecret, now: DateTime<Utc>) -> String {
+        let issued_at = now.timestamp();
+        format!("{}.{}", issued_at, sign(hmac_secret, issued_at))
+    }
+
+    #[tracing::instrument(name = "Check for bots", skip_all)]
+    pub async fn check(
+        &self,
+        submission: &Submission<'_>,
+        hmac_secret: &HmacSecret,
+        now: DateTime<Utc>,
+    ) -> Result<(), BotCheckError> {
+        if !submission.website.is_empty() {
+            return Err(BotCheckError::HoneypotFilled);
+        }
+        let form_token = submission
+            .form_token
+            .ok_or(BotCheckError::MissingFormToken)?;
+        let issued_at = verify_form_token(hmac_secret, form_token)?;
+        let elapsed = now - issued_at;
+        if elapsed > self.form_token_expiration {
+            return Err(BotCheckError::ExpiredFormToken);
+        }
+        if elapsed < self.min_submit_time {
+            return Err(BotCheckError::SubmittedTooQuickly);
+        }
+        if let Some(verifier) = &self.challenge_verifier {
+            let response = submission
+                .challenge_response
+                .ok_or(BotCheckError::ChallengeFailed)?;
+            let solved = verifier
+                .verify(response)
+                .await
+                .map_err(BotCheckError::VerifierUnavailable)?;
+            if !solved {
+                return Err(BotCheckError::ChallengeFailed);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mac(hmac_secret: &HmacSecret, issued_at: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&hmac_secret.key_for("subscription-form-tokens"))
+        .expect("HMAC can take a key of any size.");
+    mac.update(&issued_at.to_be_bytes());
+    mac
+}
+
+fn sign(hmac_secret: &HmacSecret, issued_at: i64) -> String {
+    hex::encode(mac(hmac_secret, issued_at).finalize().into_bytes())
+}
+
+/// Return when the token was issued, if we did issue it.
+fn verify_form_token(
+    hmac_secret: &HmacSecret,
+    form_token: &str,
+) -> Result<DateTime<Utc>, BotCheckError> {
+    let (issued_at, signature) = form_token
+        .split_once('.')
+        .ok_or(BotCheckError::InvalidFormToken)?;
+    let issued_at: i64 = issued_at
+        .parse()
+        .map_err(|_| BotCheckError::InvalidFormToken)?;
+    let tag = hex::decode(signature).map_err(|_| BotCheckError::InvalidFormToken)?;
+    // `verify_slice` compares in constant time
+    mac(hmac_secret, issued_at)
+        .verify_slice(&tag)
+        .map_err(|_| BotCheckError::InvalidFormToken)?;
+    Utc.timestamp_opt(issued_at, 0)
+        .single()
+        .ok_or(BotCheckError::InvalidFormToken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotCheckError, BotProtection, FakeChallengeVerifier, Submission};
+    use crate::startup::HmacSecret;
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+
+    fn protection() -> BotProtection {
+        BotProtection {
+            min_submit_time: Duration::seconds(3),
+            form_token_expiration: Duration::hours(1),
+            challenge_verifier: None,
+        }
+    }
+
+    fn secret() -> HmacSecret {
+        HmacSecret("a-secret".into())
+    }
+
+    fn submission(form_token: &str) -> Submission<'_> {
+        Submission {
+            website: "",
+            form_token: Some(form_token),
+            challenge_response: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_form_submitted_in_a_human_amount_of_time_passes() {
+        let issued_at = Utc::now();
+        let token = protection().issue_form_token(&secret(), issued_at);
+
+        let outcome = protection()
+            .check(
+                &submission(&token),
+                &secret(),
+                issued_at + Duration::seconds(10),
+            )
+            .await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_form_submitted_too_quickly_is_rejected() {
+        let issued_at = Utc::now();
+        let token = protection().issue_form_token(&secret(), issued_at);
+
+        let outcome = protection()
+            .check(
+                &submission(&token),
+                &secret(),
+                issued_at + Duration::seconds(1),
+            )
+            .await;
+
+        assert!(matches!(outcome, Err(BotCheckError::SubmittedTooQuickly)));
+    }
+
+    #[tokio::test]
+    async fn an_expired_form_token_is_rejected() {
+        let issued_at = Utc::now();
+        let token = protection().issue_form_token(&secret(), issued_at);
+
+        let outcome = protection()
+            .check(
+                &submission(&token),
+                &secret(),
+                issued_at + Duration::hours(2),
+            )
+            .await;
+
+        assert!(matches!(outcome, Err(BotCheckError::ExpiredFormToken)));
+    }
+
+    #[tokio::test]
+    async fn a_backdated_form_token_is_rejected() {
+        let now = Utc::now();
+        let token = protection().issue_form_token(&secret(), now);
+        let (_, signature) = token.split_once('.').unwrap();
+        let backdated = format!("{}.{}", (now - Duration::minutes(5)).timestamp(), signature);
+
+        let outcome = protection()
+            .check(&submission(&backdated), &secret(), now)
+            .await;
+
+        assert!(matches!(outcome, Err(BotCheckError::InvalidFormToken)));
+    }
+
+    #[tokio::test]
+    async fn a_filled_in_honeypot_is_rejected() {
+        let issued_at = Utc::now();
+        let token = protection().issue_form_token(&secret(), issued_at);
+        let submission = Submission {
+            website: "https://spam.example.com",
+            ..submission(&token)
+        };
+
+        let outcome = protection()
+            .check(&submission, &secret(), issued_at + Duration::seconds(10))
+            .await;
+
+        assert!(matches!(outcome, Err(BotCheckError::HoneypotFilled)));
+    }
+
+    #[tokio::test]
+    async fn the_challenge_must_be_solved_when_a_verifier_is_configured() {
+        let protection = BotProtection {
+            challenge_verifier: Some(Arc::new(FakeChallengeVerifier)),
+            ..protection()
+        };
+        let issued_at = Utc::now();
+        let token = protection.issue_form_token(&secret(), issued_at);
+        let later = issued_at + Duration::seconds(10);
+
+        let unsolved = Submission {
+            challenge_response: Some("beep-boop"),
+            ..submission(&token)
+        };
+        let solved = Submission {
+            challenge_response: Some(FakeChallengeVerifier::PASSING_RESPONSE),
+            ..submission(&token)
+        };
+
+        assert!(matches!(
+            protection
+                .check(&submission(&token), &secret(), later)
+                .await,
+            Err(BotCheckError::ChallengeFailed)
+        ));
+        assert!(matches!(
+            protection.check(&unsolved, &secret(), later).await,
+            Err(BotCheckError::ChallengeFailed)
+        ));
+        assert!(protection.check(&solved, &secret(), later).await.is_ok());
+    }
+}
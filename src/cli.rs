@@ -0,0 +1,214 @@
+//! Subcommands of the `zero2prod` binary.
+use crate::authentication::{change_password, compute_password_hash};
+use crate::domain::{NewPassword, SubscriberEmail};
+use crate::migrations::{migration_status, run_migrations};
+use crate::session_store::delete_user_sessions;
+use crate::telemetry::spawn_blocking_with_tracing;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(clap::Parser)]
+#[command(name = "zero2prod", version, about = "Newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Run the HTTP server and its background workers (the default)
+    Serve,
+    /// Apply pending database migrations
+    Migrate {
+        /// Only list the applied and pending migrations
+        #[arg(long)]
+        status: bool,
+    },
+    /// Manage the users who can log into the admin panel
+    #[command(subcommand)]
+    Users(UsersCommand),
+}
+
+#[derive(clap::Subcommand)]
+pub enum UsersCommand {
+    /// Create a user, prompting for their password
+    Add {
+        username: String,
+        /// Where password reset links are sent
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// List all users
+    List,
+    /// Prevent a user from logging in and end their sessions
+    Disable { username: String },
+    /// Replace a user's password, prompting for the new one
+    SetPassword { username: String },
+}
+
+pub async fn run_users_command(command: UsersCommand, pool: &PgPool) -> Result<(), anyhow::Error> {
+    match command {
+        UsersCommand::Add { username, email } => {
+            let email = email
+                .map(SubscriberEmail::parse)
+                .transpose()
+                .map_err(anyhow::Error::msg)?;
+            let password = prompt_new_password()?;
+            let user_id = add_user(pool, &username, email.as_ref(), password).await?;
+            println!("Created user {} ({}).", username, user_id);
+        }
+        UsersCommand::List => {
+            for user in list_users(pool).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    user.user_id,
+                    user.username,
+                    user.email.as_deref().unwrap_or("-"),
+                    match user.disabled_at {
+                        Some(disabled_at) => format!("disabled since {}", disabled_at),
+                        None => "active".into(),
+                    }
+                );
+            }
+        }
+        UsersCommand::Disable { username } => {
+            disable_user(pool, &username).await?;
+            println!("Disabled user {}.", username);
+        }
+        UsersCommand::SetPassword { username } => {
+            let user_id = get_active_user_id(pool, &username)
+                .await?
+                .with_context(|| format!("There is no active user named {}.", username))?;
+            let password = prompt_new_password()?;
+            change_password(user_id, password, pool).await?;
+            println!("Changed the password of {}.", username);
+        }
+    }
+    Ok(())
+}
+
+/// Print applied and pending migrations, then apply the pending ones.
+pub async fn run_migrate_command(status_only: bool, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let status = migration_status(pool).await?;
+    for migration in &status.applied {
+        println!("applied\t{}\t{}", migration.version, migration.description);
+    }
+    for migration in &status.pending {
+        println!("pending\t{}\t{}", migration.version, migration.description);
+    }
+    for version in &status.unknown {
+        println!("unknown\t{}\t-", version);
+    }
+    status.ensure_compatible()?;
+    if status_only || status.pending.is_empty() {
+        return Ok(());
+    }
+    run_migrations(pool).await?;
+    println!("Applied {} migration(s).", status.pending.len());
+    Ok(())
+}
+
+/// Read a new password twice from the terminal, without echoing it.
+fn prompt_new_password() -> Result<NewPassword, anyhow::Error> {
+    let password = rpassword::prompt_password("New password: ")?;
+    let password_check = rpassword::prompt_password("Confirm new password: ")?;
+    if password != password_check {
+        anyhow::bail!("You entered two different passwords.");
+    }
+    NewPassword::parse(password).map_err(anyhow::Error::msg)
+}
+
+#[tracing::instrument(name = "Add a user", skip(pool, email, password))]
+pub async fn add_user(
+    pool: &PgPool,
+    username: &str,
+    email: Option<&SubscriberEmail>,
+    password: NewPassword,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash,
+        email.map(|e| e.as_ref()),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert the new user in the database.")?;
+    Ok(user_id)
+}
+
+pub struct UserSummary {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "List users", skip(pool))]
+pub async fn list_users(pool: &PgPool) -> Result<Vec<UserSummary>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        UserSummary,
+        r#"
+        SELECT user_id, username, email, disabled_at
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the list of users.")?;
+    Ok(users)
+}
+
+#[tracing::instrument(name = "Disable a user", skip(pool))]
+pub async fn disable_user(pool: &PgPool, username: &str) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let user_id = sqlx::query!(
+        r#"
+        UPDATE users
+        SET disabled_at = now()
+        WHERE username = $1 AND disabled_at IS NULL
+        RETURNING user_id
+        "#,
+        username,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to disable the user.")?
+    .with_context(|| format!("There is no active user named {}.", username))?
+    .user_id;
+    delete_user_sessions(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable a user.")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Get user_id from username", skip(pool))]
+pub async fn get_active_user_id(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE username = $1 AND disabled_at IS NULL"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by username.")?;
+    Ok(row.map(|r| r.user_id))
+}
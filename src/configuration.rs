@@ -1,25 +1,314 @@
-use crate::domain::SubscriberEmail;
+use crate::bot_protection::{BotProtection, FakeChallengeVerifier};
+use crate::domain::{EmailPolicy, SubscriberEmail, DISPOSABLE_DOMAINS};
+use crate::email_client::{
+    EmailClient, FileTransport, PostmarkTransport, RetryPolicy, SmtpAuthMechanism, SmtpTls,
+    SmtpTransport,
+};
+use crate::email_templates::EmailTemplates;
+use crate::rate_limit::{InMemoryStore, PostgresStore, RateLimitStore, TokenBucket};
+use crate::telemetry::RedactionStrategy;
+use anyhow::Context;
+use ipnet::IpNet;
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
-use sqlx::ConnectOptions;
+use sqlx::{ConnectOptions, PgPool};
 use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+use std::sync::Arc;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub email_templates: EmailTemplateSettings,
+    pub idempotency: IdempotencySettings,
+    pub subscription_tokens: SubscriptionTokenSettings,
+    pub newsletter_scheduler: NewsletterSchedulerSettings,
+    pub password_reset: PasswordResetSettings,
+    pub telemetry: TelemetrySettings,
+    pub health: HealthSettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_policy: EmailPolicySettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailPolicySettings {
+    // Providers in `src/domain/disposable_domains.txt`, or in `disposable_domains_file`
+    pub block_disposable_domains: bool,
+    // Relative to the working directory: replaces the bundled list when set
+    pub disposable_domains_file: Option<String>,
+    // `noreply@`, `postmaster@` and the like
+    pub block_role_accounts: bool,
+    // Leave empty to accept any domain that is not denied
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+}
+
+impl EmailPolicySettings {
+    pub fn policy(&self) -> Result<EmailPolicy, anyhow::Error> {
+        let disposable_domains = match &self.disposable_domains_file {
+            Some(path) => std::fs::read_to_string(path).with_context(|| {
+                format!("Failed to read the list of disposable domains in {}", path)
+            })?,
+            None => DISPOSABLE_DOMAINS.to_string(),
+        };
+        Ok(EmailPolicy::new(
+            self.block_disposable_domains,
+            self.block_role_accounts,
+            &self.allowed_domains,
+            &self.denied_domains,
+            &disposable_domains,
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    // Forms submitted sooner than this after the form token was issued are rejected
+    pub min_submit_seconds: u64,
+    // How long a form token stays valid
+    pub form_token_expiration_seconds: u64,
+    // Which challenge, if any, the client has to solve
+    pub challenge_verifier: ChallengeVerifierKind,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeVerifierKind {
+    None,
+    // Local development and tests only
+    Fake,
+}
+
+impl BotProtectionSettings {
+    pub fn bot_protection(&self) -> BotProtection {
+        BotProtection {
+            min_submit_time: chrono::Duration::seconds(self.min_submit_seconds as i64),
+            form_token_expiration: chrono::Duration::seconds(
+                self.form_token_expiration_seconds as i64,
+            ),
+            challenge_verifier: match self.challenge_verifier {
+                ChallengeVerifierKind::None => None,
+                ChallengeVerifierKind::Fake => Some(Arc::new(FakeChallengeVerifier)),
+            },
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    // Where the token buckets are kept
+    pub store: RateLimitStoreKind,
+    // Ranges of the proxies whose `X-Forwarded-For` we believe
+    pub trusted_proxies: Vec<IpNet>,
+    // Subscription requests from a single client
+    pub per_ip: TokenBucket,
+    // Subscription requests for a single email address
+    pub per_email: TokenBucket,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    // Shared by every instance using the same database
+    Postgres,
+    // Per process
+    Memory,
+}
+
+impl RateLimitSettings {
+    pub fn store(&self, pool: PgPool) -> Arc<dyn RateLimitStore> {
+        match self.store {
+            RateLimitStoreKind::Postgres => Arc::new(PostgresStore::new(pool)),
+            RateLimitStoreKind::Memory => Arc::new(InMemoryStore::new()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailTemplateSettings {
+    // Relative to the working directory
+    pub directory: String,
+}
+
+impl EmailTemplateSettings {
+    pub fn templates(&self) -> Result<EmailTemplates, anyhow::Error> {
+        EmailTemplates::load(Path::new(&self.directory))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct NewsletterSchedulerSettings {
+    // How often scheduled issues are checked for being due
+    pub poll_interval_seconds: u64,
+}
+
+impl NewsletterSchedulerSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct HealthSettings {
+    // Upper bound for each of the checks behind `/health/ready`
+    pub timeout_milliseconds: u64,
+    // Whether `/health/ready` also pings the email provider
+    pub check_email_provider: bool,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    // `service.name` of the exported spans
+    pub service_name: String,
+    // Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`:
+    // spans are only exported when it is set
+    pub otlp_endpoint: Option<String>,
+    // Share of new traces that get exported, between 0.0 and 1.0.
+    // Requests carrying a `traceparent` follow the caller's decision.
+    pub sampling_ratio: f64,
+    pub redaction: RedactionSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RedactionSettings {
+    // Only the `local` environment may log personal data
+    pub enabled: bool,
+    pub strategy: RedactionStrategy,
+    // Whose values never reach the logs: `email` also covers `subscriber_email`
+    pub fields: Vec<String>,
+    // Keys the digests of the `hash` strategy: unkeyed, the digest of an
+    // email address is reversed by hashing candidate addresses
+    pub hash_key: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IdempotencySettings {
+    // How long a saved response is replayed before its key can be reused
+    pub expiration_seconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.expiration_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionTokenSettings {
+    // How long a confirmation link stays valid
+    pub expiration_seconds: u64,
+    // How often expired tokens are purged from the database
+    pub cleanup_interval_seconds: u64,
+}
+
+impl SubscriptionTokenSettings {
+    pub fn expiration(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.expiration_seconds as i64)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordResetSettings {
+    // How long a password reset link stays valid
+    pub expiration_seconds: u64,
+}
+
+impl PasswordResetSettings {
+    pub fn expiration(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.expiration_seconds as i64)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    // Which `EmailTransport` delivers our emails
+    pub transport: EmailTransportKind,
+    // Postmark only
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: String,
     pub timeout_milliseconds: u64,
+    // Retries of transient failures (timeouts, 429, 5xx)
+    pub max_retries: u32,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileTransportSettings>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    // Leave both unset for relays that do not require authentication
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: SmtpTls,
+    pub auth_mechanism: SmtpAuthMechanism,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct FileTransportSettings {
+    // Where `.eml` files are written to
+    pub directory: String,
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Arc<EmailClient> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let retry_policy = self.retry_policy();
+        match self.transport {
+            EmailTransportKind::Postmark => Arc::new(PostmarkTransport::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                retry_policy,
+            )),
+            EmailTransportKind::Smtp => {
+                let settings = self
+                    .smtp
+                    .expect("Missing `email_client.smtp` settings for the smtp transport.");
+                Arc::new(
+                    SmtpTransport::new(sender_email, settings, timeout, retry_policy)
+                        .expect("Failed to build the smtp transport."),
+                )
+            }
+            EmailTransportKind::File => {
+                let settings = self
+                    .file
+                    .expect("Missing `email_client.file` settings for the file transport.");
+                Arc::new(
+                    FileTransport::new(sender_email, settings.directory.into())
+                        .expect("Failed to create the directory for the file transport."),
+                )
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -27,6 +316,14 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: std::time::Duration::from_millis(self.retry_base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.retry_max_delay_milliseconds),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -36,6 +333,8 @@ pub struct ApplicationSettings {
     pub host: String,
     // New field!
     pub base_url: String,
+    // Key used to sign links we send out, e.g. to unsubscribe
+    pub hmac_secret: String,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -48,8 +347,14 @@ pub struct DatabaseSettings {
     pub database_name: String,
     // Determine if we demand the connection to be encrypted or not
     pub require_ssl: bool,
+    // Apply pending migrations when the application boots
+    pub migrate_on_startup: bool,
 }
 
+// The keys derived from the secret are only as strong as the secret itself
+const MIN_HMAC_SECRET_LENGTH: usize = 64;
+const MIN_REDACTION_HASH_KEY_LENGTH: usize = 32;
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     // Initialise our configuration reader
     // let mut settings = config::Config::default();
@@ -99,10 +404,34 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     // Try to convert the configuration values it read into
     // our Settings type
     // settings.try_into()
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    if settings.application.hmac_secret.len() < MIN_HMAC_SECRET_LENGTH {
+        return Err(config::ConfigError::Message(format!(
+            "`application.hmac_secret` must be at least {} bytes long: generate one with \
+            e.g. `openssl rand -hex 32`.",
+            MIN_HMAC_SECRET_LENGTH
+        )));
+    }
+    let redaction = &settings.telemetry.redaction;
+    if redaction.enabled
+        && redaction.strategy == RedactionStrategy::Hash
+        && redaction.hash_key.len() < MIN_REDACTION_HASH_KEY_LENGTH
+    {
+        return Err(config::ConfigError::Message(format!(
+            "`telemetry.redaction.hash_key` must be at least {} bytes long.",
+            MIN_REDACTION_HASH_KEY_LENGTH
+        )));
+    }
+    if !settings.telemetry.redaction.enabled && environment != Environment::Local {
+        return Err(config::ConfigError::Message(
+            "Log redaction can only be turned off in the `local` environment.".into(),
+        ));
+    }
+    Ok(settings)
 }
 
 /// The possible runtime environment for our application.
+#[derive(PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
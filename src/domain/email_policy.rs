@@ -0,0 +1,220 @@
+use crate::domain::SubscriberEmail;
+use std::collections::HashSet;
+
+/// The bundled list, used unless another one is configured.
+pub const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Mailboxes that belong to a role rather than to a person:
+/// nobody reads them, or nobody should be subscribing them.
+const ROLE_LOCAL_PARTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "security",
+    "webmaster",
+];
+
+/// Which addresses we are willing to send emails to,
+/// on top of them being syntactically valid.
+pub struct EmailPolicy {
+    block_disposable_domains: bool,
+    block_role_accounts: bool,
+    // When not empty, only these domains are accepted
+    allowed_domains: HashSet<String>,
+    denied_domains: HashSet<String>,
+    disposable_domains: HashSet<String>,
+}
+
+/// Why an address was turned down: the message is shown to the user.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum EmailPolicyViolation {
+    #[error("Email addresses at {0} are not accepted.")]
+    DeniedDomain(String),
+    #[error("Email addresses at {0} are not on the list of accepted domains.")]
+    DomainNotAllowed(String),
+    #[error("{0} is a disposable email provider: please use a permanent address.")]
+    DisposableDomain(String),
+    #[error("{0}@ is a role address: please use a personal address.")]
+    RoleAccount(String),
+}
+
+impl EmailPolicy {
+    /// `disposable_domains` has one domain per line; lines starting with `#` are comments.
+    pub fn new(
+        block_disposable_domains: bool,
+        block_role_accounts: bool,
+        allowed_domains: &[String],
+        denied_domains: &[String],
+        disposable_domains: &str,
+    ) -> Self {
+        Self {
+            block_disposable_domains,
+            block_role_accounts,
+            allowed_domains: normalise(allowed_domains.iter().map(String::as_str)),
+            denied_domains: normalise(denied_domains.iter().map(String::as_str)),
+            disposable_domains: normalise(
+                disposable_domains
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with('#')),
+            ),
+        }
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), EmailPolicyViolation> {
+        let (local_part, domain) = email
+            .as_ref()
+            .rsplit_once('@')
+            .expect("A valid email address contains an `@`.");
+        let domain = domain.to_lowercase();
+
+        if matches(&self.denied_domains, &domain) {
+            return Err(EmailPolicyViolation::DeniedDomain(domain));
+        }
+        if !self.allowed_domains.is_empty() && !matches(&self.allowed_domains, &domain) {
+            return Err(EmailPolicyViolation::DomainNotAllowed(domain));
+        }
+        if self.block_disposable_domains && matches(&self.disposable_domains, &domain) {
+            return Err(EmailPolicyViolation::DisposableDomain(domain));
+        }
+        if self.block_role_accounts {
+            // `noreply+newsletter@` is still `noreply@`
+            let mailbox = local_part
+                .split('+')
+                .next()
+                .unwrap_or(local_part)
+                .to_lowercase();
+            if ROLE_LOCAL_PARTS.contains(&mailbox.as_str()) {
+                return Err(EmailPolicyViolation::RoleAccount(mailbox));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn normalise<'a>(domains: impl Iterator<Item = &'a str>) -> HashSet<String> {
+    domains
+        .map(|domain| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// Whether `domain` is one of `domains`, or a subdomain of one of them.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailPolicy, EmailPolicyViolation, DISPOSABLE_DOMAINS};
+    use crate::configuration::EmailPolicySettings;
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err_eq, assert_ok};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.into()).unwrap()
+    }
+
+    fn default_policy() -> EmailPolicy {
+        EmailPolicy::new(true, true, &[], &[], DISPOSABLE_DOMAINS)
+    }
+
+    #[test]
+    fn personal_addresses_are_accepted() {
+        assert_ok!(default_policy().check(&email("ursula@gmail.com")));
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        assert_err_eq!(
+            default_policy().check(&email("ursula@Mailinator.com")),
+            EmailPolicyViolation::DisposableDomain("mailinator.com".into())
+        );
+        assert_err_eq!(
+            default_policy().check(&email("ursula@eu.yopmail.com")),
+            EmailPolicyViolation::DisposableDomain("eu.yopmail.com".into())
+        );
+    }
+
+    #[test]
+    fn role_addresses_are_rejected_even_with_a_tag() {
+        assert_err_eq!(
+            default_policy().check(&email("NoReply+news@example.com")),
+            EmailPolicyViolation::RoleAccount("noreply".into())
+        );
+        assert_err_eq!(
+            default_policy().check(&email("postmaster@example.com")),
+            EmailPolicyViolation::RoleAccount("postmaster".into())
+        );
+    }
+
+    #[test]
+    fn checks_can_be_turned_off() {
+        let policy = EmailPolicy::new(false, false, &[], &[], DISPOSABLE_DOMAINS);
+        assert_ok!(policy.check(&email("noreply@mailinator.com")));
+    }
+
+    #[test]
+    fn denied_domains_are_rejected() {
+        let policy = EmailPolicy::new(
+            true,
+            true,
+            &[],
+            &["competitor.com".into()],
+            DISPOSABLE_DOMAINS,
+        );
+        assert_err_eq!(
+            policy.check(&email("ursula@competitor.com")),
+            EmailPolicyViolation::DeniedDomain("competitor.com".into())
+        );
+    }
+
+    #[test]
+    fn only_allowed_domains_are_accepted_when_an_allow_list_is_set() {
+        let policy = EmailPolicy::new(true, true, &["example.com".into()], &[], DISPOSABLE_DOMAINS);
+        assert_ok!(policy.check(&email("ursula@example.com")));
+        assert_ok!(policy.check(&email("ursula@eng.example.com")));
+        assert_err_eq!(
+            policy.check(&email("ursula@gmail.com")),
+            EmailPolicyViolation::DomainNotAllowed("gmail.com".into())
+        );
+    }
+
+    #[test]
+    fn a_configured_list_replaces_the_bundled_one() {
+        let policy = EmailPolicy::new(true, true, &[], &[], "# Ours\nthrowaway.example\n");
+        assert_ok!(policy.check(&email("ursula@mailinator.com")));
+        assert_err_eq!(
+            policy.check(&email("ursula@throwaway.example")),
+            EmailPolicyViolation::DisposableDomain("throwaway.example".into())
+        );
+    }
+
+    #[test]
+    fn a_missing_list_file_is_reported() {
+        let settings = EmailPolicySettings {
+            block_disposable_domains: true,
+            disposable_domains_file: Some("does/not/exist.txt".into()),
+            block_role_accounts: true,
+            allowed_domains: vec![],
+            denied_domains: vec![],
+        };
+        assert!(settings.policy().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+/// The two bodies of a newsletter issue, as they are stored and sent.
+#[derive(Debug)]
+pub struct IssueContent {
+    html: String,
+    text: String,
+    // What they were derived from, if anything: drafts keep it for editing
+    markdown: Option<String>,
+}
+
+impl IssueContent {
+    /// Markdown is rendered to both bodies; an explicit `html` or `text`
+    /// takes precedence over what is derived from it.
+    /// Without Markdown, both `html` and `text` must be provided.
+    pub fn parse(
+        html: Option<String>,
+        text: Option<String>,
+        markdown: Option<String>,
+    ) -> Result<IssueContent, String> {
+        match (html, text, markdown) {
+            (html, text, Some(markdown)) => {
+                if markdown.trim().is_empty() {
+                    return Err("The markdown content is empty.".into());
+                }
+                Ok(Self {
+                    html: html.unwrap_or_else(|| markdown_to_html(&markdown)),
+                    text: text.unwrap_or_else(|| markdown_to_text(&markdown)),
+                    markdown: Some(markdown),
+                })
+            }
+            (Some(html), Some(text), None) => Ok(Self {
+                html,
+                text,
+                markdown: None,
+            }),
+            (None, None, None) => {
+                Err("The content is missing: provide `markdown`, or both `html` and `text`.".into())
+            }
+            (_, _, None) => Err(
+                "Provide both `html` and `text`, or `markdown` to derive the missing one.".into(),
+            ),
+        }
+    }
+
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn markdown(&self) -> Option<&str> {
+        self.markdown.as_deref()
+    }
+}
+
+/// Markdown may embed raw HTML: whatever `ammonia` deems unsafe is dropped.
+fn markdown_to_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(markdown));
+    ammonia::clean(&html)
+}
+
+/// A readable plain-text alternative: links (and images) become numbered
+/// footnotes, listed at the bottom, and raw HTML is left out.
+fn markdown_to_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<String> = Vec::new();
+    // Where the text of each open link starts, and where it points to
+    let mut open_links: Vec<(usize, String)> = Vec::new();
+    // The next number of each open list, `None` if it is unordered
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // How many inline raw HTML elements are open: their text is left out too
+    let mut open_html_elements = 0usize;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::InlineHtml(tag) => {
+                if tag.starts_with("</") {
+                    open_html_elements = open_html_elements.saturating_sub(1);
+                } else if opens_an_element(&tag) {
+                    open_html_elements += 1;
+                }
+            }
+            Event::Text(_) | Event::Code(_) if open_html_elements > 0 => {}
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        text.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::List(first)) => {
+                // A nested list starts on its own line
+                if !lists.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(first);
+            }
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) => {
+                open_links.push((text.len(), dest_url.into_string()));
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                if let Some((start, url)) = open_links.pop() {
+                    // `<https://example.com>` already reads fine
+                    if text[start..] != url {
+                        links.push(url);
+                        text.push_str(&format!(" [{}]", links.len()));
+                    }
+                }
+            }
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock) => {
+                // Elements left open do not spill over into the next block
+                open_html_elements = 0;
+                if lists.is_empty() {
+                    end_block(&mut text);
+                } else if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut text);
+                }
+            }
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => {
+                text.push_str("---");
+                end_block(&mut text);
+            }
+            _ => {}
+        }
+    }
+    let mut text = text.trim_end().to_string();
+    if !links.is_empty() {
+        text.push_str("\n\n");
+        for (i, link) in links.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", i + 1, link));
+        }
+        text.truncate(text.trim_end().len());
+    }
+    text
+}
+
+// Elements without content, so without a closing tag
+const VOID_ELEMENTS: [&str; 6] = ["br", "hr", "img", "input", "wbr", "meta"];
+
+/// `<b>` opens an element; `<br>`, `<img ... />` and `<!-- -->` do not.
+fn opens_an_element(tag: &str) -> bool {
+    let name: String = tag
+        .trim_start_matches('<')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    !name.is_empty() && !tag.trim_end().ends_with("/>") && !VOID_ELEMENTS.contains(&name.as_str())
+}
+
+/// Blocks are separated by a blank line.
+fn end_block(text: &mut String) {
+    text.truncate(text.trim_end_matches('\n').len());
+    text.push_str("\n\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::IssueContent;
+    use claims::{assert_err, assert_ok};
+
+    fn from_markdown(markdown: &str) -> IssueContent {
+        IssueContent::parse(None, None, Some(markdown.into())).unwrap()
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let content = from_markdown("# Hello\n\nSome *news*.");
+
+        assert_eq!(
+            content.html(),
+            "<h1>Hello</h1>\n<p>Some <em>news</em>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_html_in_markdown_is_removed() {
+        let content = from_markdown(
+            "Hi<script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"x\">click</a>",
+        );
+
+        assert!(!content.html().contains("script"));
+        assert!(!content.html().contains("javascript"));
+        assert!(!content.html().contains("onclick"));
+        assert!(!content.html().contains("alert"));
+        assert!(!content.text().contains("script"));
+        assert!(!content.text().contains("alert"));
+        // The second line is an HTML block: left out whole
+        assert_eq!(content.text(), "Hi");
+    }
+
+    #[test]
+    fn void_html_elements_do_not_hide_the_text_after_them() {
+        let content = from_markdown("One<br>two<img src=\"x.png\"/>three <b>bold</b> four");
+
+        assert_eq!(content.text(), "Onetwothree  four");
+    }
+
+    #[test]
+    fn the_markdown_source_is_kept() {
+        let content = from_markdown("Some *news*.");
+
+        assert_eq!(content.markdown(), Some("Some *news*."));
+    }
+
+    #[test]
+    fn links_become_footnotes_in_the_text() {
+        let content = from_markdown(
+            "Read [the post](https://example.com/post) and [the docs](https://example.com/docs).\n\n\
+            Or go to <https://example.com>.",
+        );
+
+        assert_eq!(
+            content.text(),
+            "Read the post [1] and the docs [2].\n\n\
+            Or go to https://example.com.\n\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn lists_and_headings_are_readable_in_the_text() {
+        let content = from_markdown("# News\n\n- one\n- two\n\n1. first\n2. second\n\nBye");
+
+        assert_eq!(
+            content.text(),
+            "News\n\n- one\n- two\n\n1. first\n2. second\n\nBye"
+        );
+    }
+
+    #[test]
+    fn template_variables_survive_the_rendering() {
+        let content = from_markdown("Dear {{ subscriber.name }},");
+
+        assert_eq!(content.html(), "<p>Dear {{ subscriber.name }},</p>\n");
+        assert_eq!(content.text(), "Dear {{ subscriber.name }},");
+    }
+
+    #[test]
+    fn explicit_html_and_text_take_precedence_over_markdown() {
+        let content =
+            IssueContent::parse(Some("<p>Custom</p>".into()), None, Some("Derived".into()))
+                .unwrap();
+
+        assert_eq!(content.html(), "<p>Custom</p>");
+        assert_eq!(content.text(), "Derived");
+    }
+
+    #[test]
+    fn html_and_text_without_markdown_are_valid() {
+        assert_ok!(IssueContent::parse(
+            Some("<p>Hi</p>".into()),
+            Some("Hi".into()),
+            None
+        ));
+    }
+
+    #[test]
+    fn missing_content_is_rejected() {
+        assert_err!(IssueContent::parse(None, None, None));
+        assert_err!(IssueContent::parse(Some("<p>Hi</p>".into()), None, None));
+        assert_err!(IssueContent::parse(None, Some("Hi".into()), None));
+        assert_err!(IssueContent::parse(None, None, Some("  ".into())));
+    }
+}
@@ -1,7 +1,13 @@
+mod email_policy;
+mod issue_content;
+mod new_password;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use email_policy::{EmailPolicy, EmailPolicyViolation, DISPOSABLE_DOMAINS};
+pub use issue_content::IssueContent;
+pub use new_password::NewPassword;
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,66 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A password a user wants to start using: long enough to resist guessing,
+/// short enough not to turn Argon2 into a denial of service vector.
+pub struct NewPassword(String);
+
+impl NewPassword {
+    const MIN_LENGTH: usize = 12;
+    const MAX_LENGTH: usize = 128;
+
+    pub fn parse(s: String) -> Result<NewPassword, String> {
+        let length = s.graphemes(true).count();
+        if length < Self::MIN_LENGTH {
+            Err(format!(
+                "The new password must be at least {} characters long.",
+                Self::MIN_LENGTH
+            ))
+        } else if length > Self::MAX_LENGTH {
+            Err(format!(
+                "The new password must be at most {} characters long.",
+                Self::MAX_LENGTH
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+}
+
+// Never print the password itself, not even in logs
+impl std::fmt::Debug for NewPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("NewPassword([REDACTED])")
+    }
+}
+
+impl AsRef<str> for NewPassword {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::NewPassword;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_12_grapheme_long_password_is_valid() {
+        assert_ok!(NewPassword::parse("a".repeat(12)));
+    }
+
+    #[test]
+    fn a_password_shorter_than_12_graphemes_is_rejected() {
+        assert_err!(NewPassword::parse("a".repeat(11)));
+    }
+
+    #[test]
+    fn a_128_grapheme_long_password_is_valid() {
+        assert_ok!(NewPassword::parse("ё".repeat(128)));
+    }
+
+    #[test]
+    fn a_password_longer_than_128_graphemes_is_rejected() {
+        assert_err!(NewPassword::parse("a".repeat(129)));
+    }
+}
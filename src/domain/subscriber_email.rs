@@ -65,9 +65,8 @@ mod tests {
     }
 
     #[quickcheck_macros::quickcheck]
-    fn valid_emails_are_parsed_successfully() {
-        let email = SafeEmail().fake();
-        // dbg!(&email);
-        claims::assert_ok!(SubscriberEmail::parse(email));
+    fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
+        // dbg!(&valid_email.0);
+        SubscriberEmail::parse(valid_email.0).is_ok()
     }
 }
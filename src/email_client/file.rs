@@ -0,0 +1,93 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{build_message, EmailHeader, EmailTransport, SendEmailError, SentEmail};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+/// Write every email as an `.eml` file in a directory instead of sending it.
+///
+/// Meant for local development: open the files with any mail client.
+pub struct FileTransport {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+impl FileTransport {
+    pub fn new(sender: SubscriberEmail, directory: PathBuf) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(directory.clone()),
+            directory,
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileTransport {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| SendEmailError::Permanent(e.into()))?;
+        Ok(SentEmail::default())
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        let metadata = tokio::fs::metadata(&self.directory).await?;
+        if !metadata.is_dir() || metadata.permissions().readonly() {
+            anyhow::bail!("{} is not a writable directory.", self.directory.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailTransport, FileTransport};
+    use claims::assert_ok;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let transport = FileTransport::new(sender, directory.clone()).unwrap();
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient, "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: Newsletter title"));
+        assert!(content.contains("To: recipient@example.com"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
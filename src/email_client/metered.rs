@@ -0,0 +1,52 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailHeader, EmailTransport, SendEmailError, SentEmail};
+use crate::metrics::Metrics;
+use std::sync::Arc;
+
+/// Wrap a transport to count the emails it sends and the ones it fails to send.
+pub struct MeteredTransport {
+    inner: Arc<EmailClient>,
+    metrics: Metrics,
+}
+
+impl MeteredTransport {
+    pub fn new(inner: Arc<EmailClient>, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl SendEmailError {
+    /// A low-cardinality label for the kind of failure.
+    pub fn class(&self) -> &'static str {
+        match self {
+            SendEmailError::Permanent(_) => "permanent",
+            SendEmailError::Transient { .. } => "transient",
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for MeteredTransport {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, SendEmailError> {
+        let outcome = self
+            .inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await;
+        match &outcome {
+            Ok(_) => self.metrics.email_sent(),
+            Err(e) => self.metrics.email_failed(e.class()),
+        }
+        outcome
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        self.inner.ping().await
+    }
+}
@@ -0,0 +1,199 @@
+mod file;
+mod metered;
+mod postmark;
+mod smtp;
+
+pub use file::FileTransport;
+pub use metered::MeteredTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::{SmtpAuthMechanism, SmtpTls, SmtpTransport};
+
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+/// An extra header to set on an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(Clone, Debug)]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// What the provider told us about an email it accepted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SentEmail {
+    // Postmark's `MessageID`: the other transports do not hand one out
+    pub message_id: Option<String>,
+}
+
+/// Something that can deliver an email on our behalf.
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SentEmail, SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Check that we can reach the provider, without sending anything.
+    async fn ping(&self) -> Result<(), anyhow::Error>;
+}
+
+/// Route handlers and the delivery worker only ever see the trait object:
+/// which transport sits behind it is decided by `EmailClientSettings::transport`.
+pub type EmailClient = dyn EmailTransport;
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error("The email provider rejected the request.")]
+    Permanent(#[source] anyhow::Error),
+    #[error("Failed to send the email after {attempts} attempt(s).")]
+    Transient {
+        attempts: u32,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// The outcome of a single failed delivery attempt, as classified by a transport.
+enum AttemptError {
+    Permanent(anyhow::Error),
+    Transient {
+        error: anyhow::Error,
+        // Delay requested by the provider, e.g. via `Retry-After`
+        retry_after: Option<Duration>,
+    },
+}
+
+/// How many times, and how far apart, we retry a transient failure.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that gives up after the first failure.
+    pub fn no_retries() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    /// Exponential backoff with "full jitter": a random delay between zero
+    /// and `base_delay * 2^attempt`, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let jittered = rand::thread_rng().gen_range(0..=exponential.as_millis() as u64);
+        Duration::from_millis(jittered)
+    }
+
+    /// Run `attempt` until it succeeds, fails permanently or we run out of retries.
+    async fn run<F, Fut, T>(&self, mut attempt: F) -> Result<T, SendEmailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AttemptError>>,
+    {
+        let mut n_attempt = 0;
+        loop {
+            let (error, retry_after) = match attempt().await {
+                Ok(sent) => return Ok(sent),
+                Err(AttemptError::Permanent(e)) => return Err(SendEmailError::Permanent(e)),
+                Err(AttemptError::Transient { error, retry_after }) => (error, retry_after),
+            };
+            let delay = match retry_after {
+                Some(retry_after) => retry_after,
+                None => self.backoff(n_attempt),
+            };
+            // We do not wait longer than `max_delay`, even if the provider asks us to
+            if n_attempt >= self.max_retries || delay > self.max_delay {
+                return Err(SendEmailError::Transient {
+                    attempts: n_attempt + 1,
+                    source: error,
+                });
+            }
+            tracing::warn!(
+                error.cause_chain = ?error,
+                attempt = n_attempt + 1,
+                retry_in_ms = delay.as_millis() as u64,
+                "Failed to send an email, retrying.",
+            );
+            tokio::time::sleep(delay).await;
+            n_attempt += 1;
+        }
+    }
+}
+
+/// Build a `multipart/alternative` message for the transports that speak MIME.
+fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[EmailHeader],
+) -> Result<lettre::Message, anyhow::Error> {
+    let mut message = lettre::Message::builder()
+        .from(sender.as_ref().parse()?)
+        .to(recipient.as_ref().parse()?)
+        .subject(subject)
+        .multipart(lettre::message::MultiPart::alternative_plain_html(
+            text_content.to_string(),
+            html_content.to_string(),
+        ))?;
+    for header in headers {
+        let name = lettre::message::header::HeaderName::new_from_ascii(header.name.clone())?;
+        message
+            .headers_mut()
+            .insert_raw(lettre::message::header::HeaderValue::new(
+                name,
+                header.value.clone(),
+            ));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::RetryPolicy;
+
+    #[test]
+    fn backoff_never_exceeds_max_delay() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+        };
+        for attempt in 0..40 {
+            assert!(policy.backoff(attempt) <= policy.max_delay);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::configuration::SmtpSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    build_message, AttemptError, EmailHeader, EmailTransport, RetryPolicy, SendEmailError,
+    SentEmail,
+};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+
+/// How the connection to the SMTP relay is encrypted.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, only meant for local relays such as MailHog.
+    None,
+    /// Upgrade a plain text connection with `STARTTLS` (usually port 587).
+    StartTls,
+    /// TLS from the first byte (usually port 465).
+    Implicit,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
+/// Deliver emails to an SMTP relay.
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+    retry_policy: RetryPolicy,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        sender: SubscriberEmail,
+        settings: SmtpSettings,
+        timeout: std::time::Duration,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, anyhow::Error> {
+        let tls = match settings.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(settings.host.clone())?),
+            SmtpTls::Implicit => Tls::Wrapper(TlsParameters::new(settings.host.clone())?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            .port(settings.port)
+            .tls(tls)
+            .timeout(Some(timeout));
+        if let (Some(username), Some(password)) = (settings.username, settings.password) {
+            let mechanism = match settings.auth_mechanism {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            };
+            builder = builder
+                .credentials(Credentials::new(username, password))
+                .authentication(vec![mechanism]);
+        }
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+            retry_policy,
+        })
+    }
+
+    /// A single attempt at sending the email.
+    async fn try_send(&self, message: &lettre::Message) -> Result<SentEmail, AttemptError> {
+        self.transport.send(message.clone()).await.map_err(|e| {
+            // 5xx replies and malformed input will not get better by retrying:
+            // everything else (4xx replies, timeouts, I/O errors) might.
+            if e.is_permanent() || e.is_client() {
+                AttemptError::Permanent(e.into())
+            } else {
+                AttemptError::Transient {
+                    error: e.into(),
+                    retry_after: None,
+                }
+            }
+        })?;
+        Ok(SentEmail::default())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+        self.retry_policy.run(|| self.try_send(&message)).await
+    }
+
+    /// Open a connection to the relay (`EHLO`, `STARTTLS`, `AUTH`) and close it.
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        if !self.transport.test_connection().await? {
+            anyhow::bail!("The SMTP relay did not accept the connection.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::SmtpSettings;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        EmailTransport, RetryPolicy, SendEmailError, SmtpAuthMechanism, SmtpTls, SmtpTransport,
+    };
+    use claims::assert_ok;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A bare-bones SMTP relay: it records every line it receives and
+    /// replies `rcpt_reply` to `RCPT TO`.
+    async fn fake_smtp_server(rcpt_reply: &'static str) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let transcript = Arc::new(Mutex::new(Vec::new()));
+        let lines = transcript.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = socket.into_split();
+                let mut reader = BufReader::new(reader).lines();
+                writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                let mut in_data = false;
+                // How many base64 lines of `AUTH LOGIN` we are still waiting for
+                let mut pending_login_lines = 0;
+                while let Ok(Some(line)) = reader.next_line().await {
+                    lines.lock().unwrap().push(line.clone());
+                    let reply: &str = if in_data {
+                        if line != "." {
+                            continue;
+                        }
+                        in_data = false;
+                        "250 queued\r\n"
+                    } else if pending_login_lines == 2 {
+                        pending_login_lines = 1;
+                        "334 UGFzc3dvcmQ6\r\n"
+                    } else if pending_login_lines == 1 {
+                        pending_login_lines = 0;
+                        "235 authenticated\r\n"
+                    } else if line.starts_with("EHLO") {
+                        "250-localhost\r\n250 AUTH PLAIN LOGIN\r\n"
+                    } else if line == "AUTH LOGIN" {
+                        pending_login_lines = 2;
+                        "334 VXNlcm5hbWU6\r\n"
+                    } else if line.starts_with("AUTH PLAIN") {
+                        "235 authenticated\r\n"
+                    } else if line.starts_with("RCPT TO") {
+                        rcpt_reply
+                    } else if line == "DATA" {
+                        in_data = true;
+                        "354 go ahead\r\n"
+                    } else if line == "QUIT" {
+                        "221 bye\r\n"
+                    } else {
+                        // MAIL FROM, RSET, NOOP
+                        "250 ok\r\n"
+                    };
+                    writer.write_all(reply.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        (port, transcript)
+    }
+
+    fn smtp_transport(port: u16, auth_mechanism: SmtpAuthMechanism) -> SmtpTransport {
+        let settings = SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            tls: SmtpTls::None,
+            auth_mechanism,
+        };
+        SmtpTransport::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            settings,
+            std::time::Duration::from_secs(2),
+            RetryPolicy::no_retries(),
+        )
+        .unwrap()
+    }
+
+    fn recipient() -> SubscriberEmail {
+        SubscriberEmail::parse("recipient@example.com".into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_plain() {
+        // Arrange
+        let (port, transcript) = fake_smtp_server("250 ok\r\n").await;
+        let transport = smtp_transport(port, SmtpAuthMechanism::Plain);
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient(), "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = transcript.lock().unwrap();
+        // base64("\0user\0secret")
+        assert!(transcript.contains(&"AUTH PLAIN AHVzZXIAc2VjcmV0".to_string()));
+        assert!(transcript.contains(&"RCPT TO:<recipient@example.com>".to_string()));
+        assert!(transcript.contains(&"Subject: Newsletter title".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_login() {
+        // Arrange
+        let (port, transcript) = fake_smtp_server("250 ok\r\n").await;
+        let transport = smtp_transport(port, SmtpAuthMechanism::Login);
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient(), "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = transcript.lock().unwrap();
+        assert!(transcript.contains(&"AUTH LOGIN".to_string()));
+        // base64("user") and base64("secret")
+        assert!(transcript.contains(&"dXNlcg==".to_string()));
+        assert!(transcript.contains(&"c2VjcmV0".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_5xx_reply_is_a_permanent_failure() {
+        // Arrange
+        let (port, _) = fake_smtp_server("550 no such user\r\n").await;
+        let transport = smtp_transport(port, SmtpAuthMechanism::Plain);
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient(), "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert!(matches!(outcome, Err(SendEmailError::Permanent(_))));
+    }
+
+    #[tokio::test]
+    async fn a_4xx_reply_is_a_transient_failure() {
+        // Arrange
+        let (port, _) = fake_smtp_server("451 try again later\r\n").await;
+        let transport = smtp_transport(port, SmtpAuthMechanism::Plain);
+
+        // Act
+        let outcome = transport
+            .send_email(&recipient(), "Newsletter title", "<p>Hello</p>", "Hello")
+            .await;
+
+        // Assert
+        assert!(matches!(outcome, Err(SendEmailError::Transient { .. })));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::domain::{SubscriberEmail, SubscriberName};
+use anyhow::Context;
+use minijinja::{context, Environment, UndefinedBehavior, Value};
+use std::path::Path;
+
+// Every template we render: they must all be in the templates directory
+const REQUIRED_TEMPLATES: [&str; 4] = [
+    "confirmation.html",
+    "confirmation.txt",
+    "newsletter.html",
+    "newsletter.txt",
+];
+
+/// The templates our emails are rendered from, compiled once at startup.
+///
+/// `.html` templates are auto-escaped: values we build ourselves and trust
+/// (links, the HTML of an issue) are passed in as safe strings.
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+/// Who an email is rendered for: `{{ subscriber.name }}`, `{{ subscriber.email }}`.
+#[derive(Clone, Copy)]
+pub struct Recipient<'a> {
+    pub name: &'a SubscriberName,
+    pub email: &'a SubscriberEmail,
+}
+
+/// The two alternative bodies of an email.
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+impl EmailTemplates {
+    /// Compile every template in `directory` and check that each of ours renders:
+    /// a broken template must stop the application from starting, not fail a send.
+    pub fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let mut env = Environment::new();
+        // `{{ subscriber.nmae }}` is a mistake, not an empty string
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        for (name, path) in template_files(directory, directory)? {
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read the template {}", path.display()))?;
+            env.add_template_owned(name.clone(), source)
+                .with_context(|| format!("Failed to compile the template {}", name))?;
+        }
+        let templates = Self { env };
+        templates
+            .check()
+            .with_context(|| format!("Invalid email templates in {}", directory.display()))?;
+        Ok(templates)
+    }
+
+    fn check(&self) -> Result<(), anyhow::Error> {
+        for name in REQUIRED_TEMPLATES {
+            self.env
+                .get_template(name)
+                .with_context(|| format!("The template {} is missing", name))?;
+        }
+        let (name, email) = sample_recipient();
+        let recipient = Recipient {
+            name: &name,
+            email: &email,
+        };
+        self.confirmation(recipient, "https://example.com/confirm")?;
+        self.newsletter(
+            "Title",
+            "<p>Content</p>",
+            "Content",
+            recipient,
+            "https://example.com/unsubscribe",
+        )?;
+        Ok(())
+    }
+
+    pub fn confirmation(
+        &self,
+        recipient: Recipient<'_>,
+        confirmation_url: &str,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let ctx = context! {
+            subscriber => subscriber(recipient),
+            confirmation_url => Value::from_safe_string(confirmation_url.to_owned()),
+        };
+        self.render("confirmation", ctx)
+    }
+
+    /// An issue as it lands in a subscriber's inbox.
+    ///
+    /// The content of the issue is a template itself, so that authors can
+    /// greet subscribers by name: its HTML is escaped, like any other template.
+    pub fn newsletter(
+        &self,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+        recipient: Recipient<'_>,
+        unsubscribe_url: &str,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let ctx = context! {
+            subscriber => subscriber(recipient),
+            unsubscribe_url => Value::from_safe_string(unsubscribe_url.to_owned()),
+        };
+        let html_content = self
+            .env
+            .template_from_named_str("issue.html", html_content)
+            .and_then(|t| t.render(&ctx))
+            .context("Failed to render the HTML content of the issue")?;
+        let text_content = self
+            .env
+            .template_from_named_str("issue.txt", text_content)
+            .and_then(|t| t.render(&ctx))
+            .context("Failed to render the text content of the issue")?;
+        let ctx = context! {
+            title => title,
+            content => Value::from_safe_string(html_content),
+            ..ctx
+        };
+        let html = self.render_one("newsletter.html", &ctx)?;
+        let ctx = context! {
+            content => text_content,
+            ..ctx
+        };
+        let text = self.render_one("newsletter.txt", &ctx)?;
+        Ok(RenderedEmail { html, text })
+    }
+
+    /// Check that the content of an issue renders, before it is stored:
+    /// the error is meant for its author.
+    pub fn check_issue(
+        &self,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), String> {
+        let (name, email) = sample_recipient();
+        let recipient = Recipient {
+            name: &name,
+            email: &email,
+        };
+        self.newsletter(
+            title,
+            html_content,
+            text_content,
+            recipient,
+            "https://example.com/unsubscribe",
+        )
+        .map(|_| ())
+        .map_err(|e| format!("{:#}", e))
+    }
+
+    fn render(&self, name: &str, ctx: Value) -> Result<RenderedEmail, anyhow::Error> {
+        Ok(RenderedEmail {
+            html: self.render_one(&format!("{}.html", name), &ctx)?,
+            text: self.render_one(&format!("{}.txt", name), &ctx)?,
+        })
+    }
+
+    fn render_one(&self, name: &str, ctx: &Value) -> Result<String, anyhow::Error> {
+        self.env
+            .get_template(name)
+            .and_then(|t| t.render(ctx))
+            .with_context(|| format!("Failed to render the template {}", name))
+    }
+}
+
+fn subscriber(recipient: Recipient<'_>) -> Value {
+    context! {
+        name => recipient.name.as_ref(),
+        email => recipient.email.as_ref(),
+    }
+}
+
+/// A stand-in subscriber, for previews and checks.
+pub fn sample_recipient() -> (SubscriberName, SubscriberEmail) {
+    (
+        SubscriberName::parse("Jane Doe".into()).unwrap(),
+        SubscriberEmail::parse("jane.doe@example.com".into()).unwrap(),
+    )
+}
+
+/// Every file below `directory`, named after its path relative to `root`.
+fn template_files(
+    root: &Path,
+    directory: &Path,
+) -> Result<Vec<(String, std::path::PathBuf)>, anyhow::Error> {
+    let mut files = Vec::new();
+    let entries = std::fs::read_dir(directory)
+        .with_context(|| format!("Failed to read the templates in {}", directory.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(template_files(root, &path)?);
+        } else {
+            let name = path
+                .strip_prefix(root)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_recipient, EmailTemplates, Recipient};
+    use crate::domain::SubscriberName;
+    use std::path::{Path, PathBuf};
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(Path::new("templates")).unwrap()
+    }
+
+    /// A copy of the templates directory, with `name` overwritten.
+    fn templates_with(name: &str, source: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        for entry in std::fs::read_dir("templates").unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(&path, directory.join(path.file_name().unwrap())).unwrap();
+        }
+        std::fs::write(directory.join(name), source).unwrap();
+        directory
+    }
+
+    #[test]
+    fn subscribers_are_greeted_by_name_and_their_name_is_escaped() {
+        let name = SubscriberName::parse("Tom & Jerry's".into()).unwrap();
+        let (_, email) = sample_recipient();
+        let recipient = Recipient {
+            name: &name,
+            email: &email,
+        };
+
+        let email = templates()
+            .newsletter(
+                "Title",
+                "<p>Hello {{ subscriber.name }}!</p>",
+                "Hello {{ subscriber.name }}!",
+                recipient,
+                "https://example.com/unsubscribe?subscriber_id=1&token=2",
+            )
+            .unwrap();
+
+        assert!(email.html.contains("<p>Hello Tom &amp; Jerry&#x27;s!</p>"));
+        assert!(email
+            .html
+            .contains(r#"<a href="https://example.com/unsubscribe?subscriber_id=1&token=2">"#));
+        assert!(email.text.contains("Hello Tom & Jerry's!"));
+    }
+
+    #[test]
+    fn a_template_that_does_not_compile_fails_to_load() {
+        let directory = templates_with("confirmation.html", "{% if %}");
+
+        assert!(EmailTemplates::load(&directory).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_template_using_an_unknown_variable_fails_to_load() {
+        let directory = templates_with("confirmation.txt", "Hi {{ subscriber.nmae }}");
+
+        assert!(EmailTemplates::load(&directory).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn issues_referring_to_unknown_variables_are_rejected() {
+        let outcome = templates().check_issue("Title", "<p>{{ subscriber.age }}</p>", "Hi");
+
+        assert!(outcome.is_err());
+    }
+}
@@ -0,0 +1,378 @@
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_client::{EmailClient, EmailHeader, SentEmail};
+use crate::email_templates::{EmailTemplates, Recipient, RenderedEmail};
+use crate::request_id::RequestId;
+use crate::routes::unsubscribe_link;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::field::display;
+use tracing::Span;
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Keep draining `issue_delivery_queue` until the process is stopped.
+///
+/// Several replicas can run this loop against the same database:
+/// `dequeue_task` skips rows that are already locked by another worker.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Arc<EmailClient>,
+    email_templates: Arc<EmailTemplates>,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &email_templates,
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Enqueue one delivery task per confirmed subscriber, and start tracking
+/// its delivery in `newsletter_deliveries`.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    request_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email,
+            request_id
+        )
+        SELECT $1, email, $2
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+        request_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            state,
+            updated_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'queued', now()
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty,
+        request_id=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, task) = task.unwrap();
+    let DeliveryTask {
+        issue_id,
+        email,
+        request_id,
+    } = task;
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("subscriber_email", display(&email));
+    // Tasks enqueued before request ids were stored have none
+    let request_id = request_id.and_then(|id| RequestId::parse(&id).ok());
+    if let Some(request_id) = &request_id {
+        Span::current().record("request_id", display(request_id));
+    }
+
+    let outcome = match SubscriberEmail::parse(email.clone()) {
+        Ok(email) => match get_confirmed_subscriber(pool, email.as_ref()).await? {
+            // They unsubscribed after the issue was enqueued
+            None => {
+                tracing::info!("Skipping a subscriber who is no longer confirmed.");
+                DeliveryOutcome::Skipped
+            }
+            Some(subscriber) => {
+                let issue = get_issue(pool, issue_id).await?;
+                let link = unsubscribe_link(&base_url.0, hmac_secret, subscriber.id);
+                let rendered = SubscriberName::parse(subscriber.name).and_then(|name| {
+                    let recipient = Recipient {
+                        name: &name,
+                        email: &email,
+                    };
+                    email_templates
+                        .newsletter(
+                            &issue.title,
+                            &issue.html_content,
+                            &issue.text_content,
+                            recipient,
+                            &link,
+                        )
+                        .map_err(|e| format!("{:#}", e))
+                });
+                match rendered {
+                    Ok(rendered) => {
+                        send_issue(
+                            email_client,
+                            &email,
+                            &issue.title,
+                            &rendered,
+                            &link,
+                            request_id.as_ref(),
+                        )
+                        .await
+                    }
+                    Err(e) => {
+                        tracing::error!(
+                            error.message = %e,
+                            "Failed to render the issue for a confirmed subscriber. \
+                            Skipping.",
+                        );
+                        DeliveryOutcome::Failed(e)
+                    }
+                }
+            }
+        },
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+            DeliveryOutcome::Failed(e)
+        }
+    };
+    record_delivery(&mut transaction, issue_id, &email, outcome).await?;
+    delete_task(transaction, issue_id, &email).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send_issue(
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    title: &str,
+    rendered: &RenderedEmail,
+    unsubscribe_link: &str,
+    request_id: Option<&RequestId>,
+) -> DeliveryOutcome {
+    let mut headers = vec![
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!("<{}>", unsubscribe_link),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ];
+    headers.extend(request_id.map(RequestId::email_header));
+    match email_client
+        .send_email_with_headers(email, title, &rendered.html, &rendered.text, &headers)
+        .await
+    {
+        Ok(sent) => DeliveryOutcome::Sent(sent),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Skipping.",
+            );
+            DeliveryOutcome::Failed(format!("{:#}", anyhow::Error::new(e)))
+        }
+    }
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+enum DeliveryOutcome {
+    Sent(SentEmail),
+    // The error, as shown in the delivery report
+    Failed(String),
+    // The subscriber is no longer a recipient
+    Skipped,
+}
+
+struct DeliveryTask {
+    issue_id: Uuid,
+    email: String,
+    // The request that published the issue
+    request_id: Option<String>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // `SKIP LOCKED` lets concurrent workers pick different rows
+    // instead of queueing up behind the one holding the lock.
+    let r = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, request_id
+        FROM issue_delivery_queue
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    if let Some(r) = r {
+        Ok(Some((
+            transaction,
+            DeliveryTask {
+                issue_id: r.newsletter_issue_id,
+                email: r.subscriber_email,
+                request_id: r.request_id,
+            },
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Update the delivery in `newsletter_deliveries`, in the transaction
+/// that removes the task from the queue.
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    // Skipping a recipient is not an attempt at sending them anything
+    let (state, attempted, last_error, message_id) = match outcome {
+        DeliveryOutcome::Sent(sent) => ("sent", 1, None, sent.message_id),
+        DeliveryOutcome::Failed(error) => ("failed", 1, Some(error), None),
+        DeliveryOutcome::Skipped => ("skipped", 0, None, None),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_deliveries
+        SET
+            state = $3,
+            attempts = attempts + $4,
+            last_error = $5,
+            provider_message_id = COALESCE($6, provider_message_id),
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        issue_id,
+        email,
+        state,
+        attempted,
+        last_error,
+        message_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"SELECT id, name FROM subscriptions WHERE email = $1 AND status = 'confirmed'"#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
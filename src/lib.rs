@@ -1,9 +1,25 @@
 #![allow(clippy::toplevel_ref_arg)]
 extern crate core;
 
+pub mod authentication;
+pub mod bot_protection;
+pub mod cli;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod metrics;
+pub mod migrations;
+pub mod newsletter_scheduler;
+pub mod problem;
+pub mod rate_limit;
+pub mod request_id;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod subscription_token_cleanup;
 pub mod telemetry;
+pub mod utils;
@@ -1,17 +1,65 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use clap::Parser;
+use zero2prod::cli::{run_migrate_command, run_users_command, Cli, Command};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::telemetry::{
+    get_subscriber, get_tracer_provider, init_subscriber, RedactingMakeWriter,
+};
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
 
     // Panic if we can't read configuration
     let configuration = get_configuration().expect("Failed to read configuration.");
 
-    // Launch application
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    // Admin commands print their results on stdout: keep logs out of the way
+    let tracer_provider = match command {
+        Command::Serve => {
+            let tracer_provider = get_tracer_provider(&configuration.telemetry)?;
+            init_subscriber(get_subscriber(
+                "zero2prod".into(),
+                "info".into(),
+                RedactingMakeWriter::new(std::io::stdout, &configuration.telemetry.redaction),
+                tracer_provider.as_ref(),
+            ));
+            tracer_provider
+        }
+        _ => {
+            init_subscriber(get_subscriber(
+                "zero2prod".into(),
+                "warn".into(),
+                RedactingMakeWriter::new(std::io::stderr, &configuration.telemetry.redaction),
+                None,
+            ));
+            None
+        }
+    };
+
+    let outcome = run(command, configuration).await;
+    // Flush the spans that are still waiting to be exported
+    if let Some(tracer_provider) = tracer_provider {
+        tracer_provider.shutdown()?;
+    }
+    outcome
+}
+
+async fn run(command: Command, configuration: Settings) -> anyhow::Result<()> {
+    match command {
+        Command::Serve => {
+            // Launch application
+            let application = Application::build(configuration).await?;
+            application.run_until_stopped().await?;
+        }
+        Command::Migrate { status } => {
+            let pool = get_connection_pool(&configuration);
+            run_migrate_command(status, &pool).await?;
+        }
+        Command::Users(command) => {
+            let pool = get_connection_pool(&configuration);
+            run_users_command(command, &pool).await?;
+        }
+    }
     Ok(())
 }
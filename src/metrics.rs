@@ -0,0 +1,164 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::time::Instant;
+
+/// Everything we expose on `/metrics`.
+///
+/// Each `Application` owns its registry (rather than using the global one)
+/// so that several instances can live in the same process, e.g. in tests.
+/// Cloning is cheap: metrics are reference-counted.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_acquire_wait_seconds: Gauge,
+    emails_sent_total: IntCounter,
+    emails_failed_total: IntCounterVec,
+    subscriptions_created_total: IntCounter,
+    subscriptions_confirmed_total: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("zero2prod".into()), None)?;
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests, by route and status"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests, by route",
+            ),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently held by the Postgres pool",
+        )?;
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Idle connections in the Postgres pool",
+        )?;
+        let db_pool_acquire_wait_seconds = Gauge::new(
+            "db_pool_acquire_wait_seconds",
+            "Time it took to acquire a connection from the pool when last scraped",
+        )?;
+        let emails_sent_total = IntCounter::new("emails_sent_total", "Emails delivered")?;
+        let emails_failed_total = IntCounterVec::new(
+            Opts::new(
+                "emails_failed_total",
+                "Emails we gave up on, by class of error",
+            ),
+            &["error_class"],
+        )?;
+        let subscriptions_created_total = IntCounter::new(
+            "subscriptions_created_total",
+            "New subscribers (pending confirmation)",
+        )?;
+        let subscriptions_confirmed_total = IntCounter::new(
+            "subscriptions_confirmed_total",
+            "Subscriptions confirmed via the link in the welcome email",
+        )?;
+
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_request_duration_seconds.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_idle_connections.clone()))?;
+        registry.register(Box::new(db_pool_acquire_wait_seconds.clone()))?;
+        registry.register(Box::new(emails_sent_total.clone()))?;
+        registry.register(Box::new(emails_failed_total.clone()))?;
+        registry.register(Box::new(subscriptions_created_total.clone()))?;
+        registry.register(Box::new(subscriptions_confirmed_total.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_acquire_wait_seconds,
+            emails_sent_total,
+            emails_failed_total,
+            subscriptions_created_total,
+            subscriptions_confirmed_total,
+        })
+    }
+
+    pub fn email_sent(&self) {
+        self.emails_sent_total.inc();
+    }
+
+    pub fn email_failed(&self, error_class: &str) {
+        self.emails_failed_total
+            .with_label_values(&[error_class])
+            .inc();
+    }
+
+    pub fn subscription_created(&self) {
+        self.subscriptions_created_total.inc();
+    }
+
+    pub fn subscription_confirmed(&self) {
+        self.subscriptions_confirmed_total.inc();
+    }
+
+    /// Pool gauges are sampled when `/metrics` is scraped.
+    pub async fn observe_pool(&self, pool: &PgPool) {
+        self.db_pool_connections.set(pool.size() as i64);
+        self.db_pool_idle_connections.set(pool.num_idle() as i64);
+        let start = Instant::now();
+        // A failure shows up as a wait as long as the acquire timeout
+        let _ = pool.acquire().await;
+        self.db_pool_acquire_wait_seconds
+            .set(start.elapsed().as_secs_f64());
+    }
+
+    /// Encode all metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("The text format is valid UTF8."))
+    }
+}
+
+/// Count requests and time them, labelled by route pattern
+/// (e.g. `/subscriptions/confirm`) to keep cardinality bounded.
+pub async fn track_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let outcome = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        // Routing happens further down the stack: the pattern is only
+        // known once the request has been through it.
+        let (route, status) = match &outcome {
+            Ok(response) => (response.request().match_pattern(), response.status()),
+            Err(e) => (None, e.as_response_error().status_code()),
+        };
+        let route = route.unwrap_or_else(|| "unmatched".into());
+        metrics
+            .http_requests_total
+            .with_label_values(&[&method, &route, status.as_str()])
+            .inc();
+        metrics
+            .http_request_duration_seconds
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+    }
+    outcome
+}
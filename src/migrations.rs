@@ -0,0 +1,90 @@
+//! Database migrations, embedded in the binary at compile time.
+use anyhow::Context;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+use std::collections::{BTreeSet, HashSet};
+
+/// Every migration in `migrations/`: no need for `sqlx-cli` where we deploy.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub struct MigrationVersion {
+    pub version: i64,
+    pub description: String,
+}
+
+/// How the database compares with the migrations this binary knows about.
+pub struct MigrationStatus {
+    pub applied: Vec<MigrationVersion>,
+    pub pending: Vec<MigrationVersion>,
+    /// Applied to the database, but missing from this binary:
+    /// the database has been migrated by a newer release.
+    pub unknown: Vec<i64>,
+}
+
+impl MigrationStatus {
+    /// Refuse to run against a schema we know nothing about.
+    pub fn ensure_compatible(&self) -> Result<(), anyhow::Error> {
+        if !self.unknown.is_empty() {
+            anyhow::bail!(
+                "The database has migrations this binary does not know about ({:?}): \
+                it is newer than this release.",
+                self.unknown
+            );
+        }
+        Ok(())
+    }
+}
+
+#[tracing::instrument(name = "Compare the database with the embedded migrations", skip(pool))]
+pub async fn migration_status(pool: &PgPool) -> Result<MigrationStatus, anyhow::Error> {
+    let applied_versions = applied_versions(pool).await?;
+    let (applied, pending) = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationVersion {
+            version: m.version,
+            description: m.description.to_string(),
+        })
+        .partition(|m| applied_versions.contains(&m.version));
+    let known: HashSet<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+    let unknown = applied_versions
+        .into_iter()
+        .filter(|version| !known.contains(version))
+        .collect();
+    Ok(MigrationStatus {
+        applied,
+        pending,
+        unknown,
+    })
+}
+
+async fn applied_versions(pool: &PgPool) -> Result<BTreeSet<i64>, anyhow::Error> {
+    // A brand new database: nothing has been applied yet
+    let has_migrations_table =
+        sqlx::query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "exists!""#)
+            .fetch_one(pool)
+            .await
+            .context("Failed to look for the migrations table.")?;
+    if !has_migrations_table {
+        return Ok(BTreeSet::new());
+    }
+    let versions = sqlx::query_scalar!("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(pool)
+        .await
+        .context("Failed to list the applied migrations.")?;
+    Ok(versions.into_iter().collect())
+}
+
+/// Apply the pending migrations, unless the database is ahead of us.
+///
+/// `sqlx` holds an advisory lock while migrating: replicas booting at the
+/// same time wait for each other instead of racing.
+#[tracing::instrument(name = "Apply pending migrations", skip(pool))]
+pub async fn run_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    migration_status(pool).await?.ensure_compatible()?;
+    MIGRATOR
+        .run(pool)
+        .await
+        .context("Failed to apply the database migrations.")?;
+    Ok(())
+}
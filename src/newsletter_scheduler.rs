@@ -0,0 +1,62 @@
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Dispatch scheduled newsletter issues as they fall due, checking every
+/// `interval` until the process is stopped.
+///
+/// Schedules live in `newsletter_issues`, so none is lost across restarts,
+/// and several replicas can run this loop against the same database:
+/// `dispatch_due_issues` skips issues that another replica is dispatching.
+pub async fn run_scheduler_until_stopped(
+    pool: PgPool,
+    interval: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by `dispatch_due_issues`: we try again next time.
+        let _ = dispatch_due_issues(&pool).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Enqueue the deliveries of every scheduled issue whose `send_at` has passed
+/// and mark it as published, returning how many issues were dispatched.
+#[tracing::instrument(skip_all, fields(n_dispatched=tracing::field::Empty), err)]
+pub async fn dispatch_due_issues(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // Locked until we commit: an issue is dispatched exactly once,
+    // and cannot be rescheduled or cancelled while it is being dispatched.
+    let due_issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, request_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+    for issue in &due_issues {
+        enqueue_delivery_tasks(
+            &mut transaction,
+            issue.newsletter_issue_id,
+            issue.request_id.as_deref(),
+        )
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = 'published', published_at = now()
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue.newsletter_issue_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    let n_dispatched = due_issues.len() as u64;
+    tracing::Span::current().record("n_dispatched", n_dispatched);
+    Ok(n_dispatched)
+}
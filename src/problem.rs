@@ -0,0 +1,107 @@
+use crate::request_id::RequestId;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpResponse};
+
+/// The error envelope of our API, as per RFC 7807 (`application/problem+json`).
+///
+/// `detail` is meant for the client: it is only filled in for errors the
+/// client can fix (e.g. an invalid email address). The causes of unexpected
+/// errors stay in our logs.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    // Quote it when reporting an issue: it is on every log line of the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, type_: &str, title: &str) -> Self {
+        Self {
+            type_: format!("/problems/{}", type_),
+            title: title.into(),
+            status: status.as_u16(),
+            detail: None,
+            request_id: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn validation(detail: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "validation-error",
+            "Your request parameters didn't validate.",
+        )
+        .with_detail(detail)
+    }
+
+    pub fn internal() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal-error",
+            "Something went wrong on our side.",
+        )
+    }
+
+    /// The request id is only known once the response makes its way back
+    /// through `attach_request_id`: the problem travels along in the
+    /// response extensions until then.
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .body(self.to_json());
+        response.extensions_mut().insert(self);
+        response
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Failed to serialize a problem.")
+    }
+}
+
+/// Fill in the `request_id` of problem responses.
+pub async fn attach_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.to_string());
+    match next.call(req).await {
+        Ok(response) => {
+            let (request, response) = response.into_parts();
+            let response = with_request_id(response.map_into_boxed_body(), request_id);
+            Ok(ServiceResponse::new(request, response))
+        }
+        // Errors of the middlewares we wrap (e.g. rate limiting) are only
+        // turned into responses by the server: render them now.
+        Err(e) => {
+            let response = with_request_id(e.error_response(), request_id);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+fn with_request_id(response: HttpResponse, request_id: Option<String>) -> HttpResponse {
+    let problem = response.extensions().get::<Problem>().cloned();
+    match (problem, request_id) {
+        (Some(mut problem), Some(request_id)) => {
+            problem.request_id = Some(request_id);
+            response.set_body(BoxBody::new(problem.to_json()))
+        }
+        _ => response,
+    }
+}
@@ -0,0 +1,57 @@
+use crate::rate_limit::{BucketState, Decision, RateLimitStore, TokenBucket};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Past this many buckets, the ones that have been refilled are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Buckets held by this process only: for single-instance deployments and tests.
+#[derive(Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, (TokenBucket, BucketState)>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(&self, key: &str, bucket: &TokenBucket) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            // A full bucket is the same as no bucket at all
+            buckets
+                .retain(|_, (bucket, state)| bucket.refill(*state, now) < bucket.capacity as f64);
+        }
+        let (state, decision) = bucket.take(buckets.get(key).map(|(_, state)| *state), now);
+        buckets.insert(key.to_owned(), (*bucket, state));
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{Decision, InMemoryStore, RateLimitStore, TokenBucket};
+    use claims::assert_ok_eq;
+
+    #[tokio::test]
+    async fn buckets_are_kept_apart_by_key() {
+        let store = InMemoryStore::new();
+        let bucket = TokenBucket {
+            capacity: 1,
+            refill_interval_seconds: 3600,
+        };
+
+        assert_ok_eq!(store.acquire("a", &bucket).await, Decision::Allowed);
+        assert!(matches!(
+            store.acquire("a", &bucket).await,
+            Ok(Decision::Limited { .. })
+        ));
+        assert_ok_eq!(store.acquire("b", &bucket).await, Decision::Allowed);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::problem::Problem;
+use crate::rate_limit::{Decision, RateLimitStore, TokenBucket};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::web;
+use ipnet::IpNet;
+use sha2::Digest;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The limits enforced by `limit_subscriptions`.
+pub struct SubscriptionRateLimits {
+    pub store: Arc<dyn RateLimitStore>,
+    // Proxies whose `X-Forwarded-For` we believe
+    pub trusted_proxies: Vec<IpNet>,
+    pub per_ip: TokenBucket,
+    pub per_email: TokenBucket,
+}
+
+#[derive(serde::Deserialize)]
+struct TargetEmail {
+    email: String,
+}
+
+/// Reject subscription requests beyond the per-IP or the per-email limit
+/// with a `429 Too Many Requests`, before any email is sent.
+pub async fn limit_subscriptions(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let limits = match req.app_data::<web::Data<SubscriptionRateLimits>>() {
+        Some(limits) => limits.clone(),
+        None => return Ok(next.call(req).await?.map_into_boxed_body()),
+    };
+
+    if let Some(ip) = client_ip(&req, &limits.trusted_proxies) {
+        let key = format!("subscriptions:ip:{}", digest(&ip.to_string()));
+        if let Some(retry_after) = limits.check(&key, &limits.per_ip).await {
+            return Ok(req.into_response(too_many_requests(retry_after)));
+        }
+    }
+
+    // The form is read here and handed over to `subscribe` untouched
+    let body = req.extract::<web::Bytes>().await?;
+    let target = serde_urlencoded::from_bytes::<TargetEmail>(&body).ok();
+    req.set_payload(Payload::from(body));
+    if let Some(target) = target {
+        let key = format!(
+            "subscriptions:email:{}",
+            digest(&target.email.trim().to_lowercase())
+        );
+        if let Some(retry_after) = limits.check(&key, &limits.per_email).await {
+            return Ok(req.into_response(too_many_requests(retry_after)));
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+/// IPs and email addresses are personal data: only their digest is stored.
+fn digest(value: &str) -> String {
+    format!("{:x}", sha2::Sha256::digest(value.as_bytes()))
+}
+
+impl SubscriptionRateLimits {
+    /// `None` if the request may go through. We fail open: an outage of
+    /// the store must not take subscriptions down with it.
+    async fn check(&self, key: &str, bucket: &TokenBucket) -> Option<Duration> {
+        match self.store.acquire(key, bucket).await {
+            Ok(Decision::Allowed) => None,
+            Ok(Decision::Limited { retry_after }) => Some(retry_after),
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to check a rate limit. Letting the request through."
+                );
+                None
+            }
+        }
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> actix_web::HttpResponse {
+    // `Retry-After` is in whole seconds: round up
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = Problem::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate-limited",
+        "Too many requests.",
+    )
+    .with_detail(format!("Try again in {} seconds.", seconds))
+    .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, seconds.to_string().parse().unwrap());
+    response
+}
+
+/// The address of the client: the peer, unless the peer is a trusted proxy,
+/// in which case we walk `X-Forwarded-For` back to the first hop we do not trust.
+fn client_ip(req: &ServiceRequest, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+    let forwarded_for = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| hop.trim().parse::<IpAddr>())
+        .collect::<Result<Vec<_>, _>>();
+    match forwarded_for {
+        Ok(hops) => Some(
+            hops.into_iter()
+                .rev()
+                .find(|hop| !is_trusted(hop))
+                // Every hop is one of our proxies
+                .unwrap_or(peer),
+        ),
+        // A proxy we trust would not forward garbage
+        Err(_) => Some(peer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client_ip;
+    use actix_web::test::TestRequest;
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .to_srv_request();
+
+        assert_eq!(
+            client_ip(&req, &["10.0.0.0/8".parse().unwrap()]),
+            Some(ip("203.0.113.7"))
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_walked_back_to_the_first_untrusted_hop() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.2:4000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "1.2.3.4, 198.51.100.1, 10.0.0.1"))
+            .to_srv_request();
+
+        // `1.2.3.4` was made up by the client: it cannot be trusted
+        assert_eq!(
+            client_ip(&req, &["10.0.0.0/8".parse().unwrap()]),
+            Some(ip("198.51.100.1"))
+        );
+    }
+}
@@ -0,0 +1,153 @@
+mod memory;
+mod middleware;
+mod postgres;
+
+pub use memory::InMemoryStore;
+pub use middleware::{limit_subscriptions, SubscriptionRateLimits};
+pub use postgres::{delete_full_buckets, PostgresStore};
+
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// `capacity` requests in a burst, then one more every `refill_interval_seconds`.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct TokenBucket {
+    pub capacity: u32,
+    pub refill_interval_seconds: u64,
+}
+
+/// What is left in a bucket, as of `updated_at`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl TokenBucket {
+    fn refill_interval(&self) -> f64 {
+        // A zero interval would refill instantly: treat it as one second
+        self.refill_interval_seconds.max(1) as f64
+    }
+
+    /// How many tokens the bucket holds at `now`.
+    pub fn refill(&self, state: BucketState, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - state.updated_at)
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+            .as_secs_f64();
+        (state.tokens + elapsed / self.refill_interval()).min(self.capacity as f64)
+    }
+
+    /// When the bucket will hold `capacity` tokens again: it is then no
+    /// different from a bucket we have never seen.
+    pub fn full_at(&self, state: BucketState) -> DateTime<Utc> {
+        let missing = (self.capacity as f64 - state.tokens).max(0.0);
+        let until_full = missing * self.refill_interval();
+        state.updated_at + chrono::Duration::milliseconds((until_full * 1000.0) as i64)
+    }
+
+    /// Try to take a token out of the bucket: `state` is `None` for a
+    /// bucket we have not seen yet, which starts full.
+    pub fn take(&self, state: Option<BucketState>, now: DateTime<Utc>) -> (BucketState, Decision) {
+        let tokens = match state {
+            None => self.capacity as f64,
+            Some(state) => self.refill(state, now),
+        };
+        if tokens >= 1.0 {
+            let state = BucketState {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            (state, Decision::Allowed)
+        } else {
+            let retry_after = Duration::from_secs_f64((1.0 - tokens) * self.refill_interval());
+            let state = BucketState {
+                tokens,
+                updated_at: now,
+            };
+            (state, Decision::Limited { retry_after })
+        }
+    }
+}
+
+/// Where the buckets live.
+///
+/// Instances behind the same load balancer must share their buckets
+/// (`PostgresStore`) for the limits to hold.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token out of the bucket identified by `key`.
+    async fn acquire(&self, key: &str, bucket: &TokenBucket) -> Result<Decision, anyhow::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, TokenBucket};
+    use chrono::{Duration, Utc};
+
+    fn bucket() -> TokenBucket {
+        TokenBucket {
+            capacity: 2,
+            refill_interval_seconds: 60,
+        }
+    }
+
+    #[test]
+    fn a_new_bucket_allows_a_burst_of_capacity_requests() {
+        let now = Utc::now();
+        let (state, first) = bucket().take(None, now);
+        let (state, second) = bucket().take(Some(state), now);
+        let (_, third) = bucket().take(Some(state), now);
+
+        assert_eq!(first, Decision::Allowed);
+        assert_eq!(second, Decision::Allowed);
+        assert_eq!(
+            third,
+            Decision::Limited {
+                retry_after: std::time::Duration::from_secs(60)
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let now = Utc::now();
+        let (state, _) = bucket().take(None, now);
+        let (state, _) = bucket().take(Some(state), now);
+
+        let (state, decision) = bucket().take(Some(state), now + Duration::seconds(45));
+        match decision {
+            Decision::Limited { retry_after } => assert_eq!(retry_after.as_secs(), 15),
+            Decision::Allowed => panic!("The bucket should still be empty."),
+        }
+        let (_, decision) = bucket().take(Some(state), now + Duration::seconds(60));
+        assert_eq!(decision, Decision::Allowed);
+    }
+
+    #[test]
+    fn a_bucket_is_full_again_once_every_token_taken_is_refilled() {
+        let now = Utc::now();
+        let (state, _) = bucket().take(None, now);
+        let (state, _) = bucket().take(Some(state), now);
+
+        assert_eq!(bucket().full_at(state), now + Duration::seconds(120));
+    }
+
+    #[test]
+    fn a_bucket_never_holds_more_than_its_capacity() {
+        let now = Utc::now();
+        let (state, _) = bucket().take(None, now);
+        let later = now + Duration::days(1);
+
+        let (state, _) = bucket().take(Some(state), later);
+        let (state, _) = bucket().take(Some(state), later);
+        let (_, decision) = bucket().take(Some(state), later);
+        assert!(matches!(decision, Decision::Limited { .. }));
+    }
+}
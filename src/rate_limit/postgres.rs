@@ -0,0 +1,90 @@
+use crate::rate_limit::{BucketState, Decision, RateLimitStore, TokenBucket};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+/// Buckets in `rate_limit_buckets`, shared by every instance using the database.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresStore {
+    #[tracing::instrument(name = "Take a rate limit token", skip(self, bucket))]
+    async fn acquire(&self, key: &str, bucket: &TokenBucket) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        // Make sure there is a row to lock, even for the very first request
+        sqlx::query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            key,
+            bucket.capacity as f64,
+            now
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to create a rate limit bucket.")?;
+        let row = sqlx::query!(
+            r#"
+            SELECT tokens, updated_at
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .context("Failed to lock a rate limit bucket.")?;
+        let state = BucketState {
+            tokens: row.tokens,
+            updated_at: row.updated_at,
+        };
+        let (state, decision) = bucket.take(Some(state), now);
+        sqlx::query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = $3, full_at = $4
+            WHERE key = $1
+            "#,
+            key,
+            state.tokens,
+            state.updated_at,
+            bucket.full_at(state)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to update a rate limit bucket.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to update a rate limit bucket.")?;
+        Ok(decision)
+    }
+}
+
+/// Drop the buckets that have been refilled: a missing bucket starts full.
+/// Without it, the table would grow with every client we have ever seen.
+#[tracing::instrument(skip_all, fields(n_deleted=tracing::field::Empty), err)]
+pub async fn delete_full_buckets(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(r#"DELETE FROM rate_limit_buckets WHERE full_at <= now()"#)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    tracing::Span::current().record("n_deleted", n_deleted);
+    Ok(n_deleted)
+}
@@ -0,0 +1,156 @@
+use crate::email_client::EmailHeader;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Ready};
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The id a support ticket quotes to find a request in our logs.
+///
+/// It comes from the `X-Request-Id` header when a proxy in front of us
+/// already assigned one, and is generated otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// An id from upstream ends up in our logs and in email headers:
+    /// only short, printable tokens are accepted.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.is_empty() || s.len() > 128 {
+            return Err("A request id must be between 1 and 128 characters long.".into());
+        }
+        if !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+        {
+            return Err(
+                "A request id can only contain ASCII letters, digits, '-', '_', '.' and ':'."
+                    .into(),
+            );
+        }
+        Ok(Self(s.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Attached to outgoing emails, to follow a request all the way to the inbox.
+    pub fn email_header(&self) -> EmailHeader {
+        EmailHeader {
+            name: REQUEST_ID_HEADER.into(),
+            value: self.0.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError(
+                "`propagate_request_id` is not registered as a middleware.",
+            )
+        }))
+    }
+}
+
+/// Pick the id of the incoming request and echo it back on the response.
+///
+/// It has to run before `TracingLogger`, which records it on the root span.
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| RequestId::parse(value).ok())
+        .unwrap_or_else(RequestId::generate);
+    req.extensions_mut().insert(request_id.clone());
+
+    let header_value =
+        HeaderValue::from_str(request_id.as_str()).expect("Request ids are valid header values.");
+    match next.call(req).await {
+        Ok(response) => {
+            let mut response = response.map_into_boxed_body();
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static("x-request-id"), header_value);
+            Ok(response)
+        }
+        // Handler errors have already been turned into responses at this point,
+        // but not those of middlewares (e.g. the redirect of anonymous users)
+        Err(e) => {
+            let mut response = e.error_response();
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static("x-request-id"), header_value);
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
+
+/// `TracingLogger`'s root span, with our request id instead of its own.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let span = tracing_actix_web::root_span!(request);
+        if let Some(request_id) = request.extensions().get::<RequestId>() {
+            span.record("request_id", request_id.as_str());
+        }
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn a_uuid_is_a_valid_request_id() {
+        assert_ok!(RequestId::parse(&RequestId::generate().to_string()));
+    }
+
+    #[test]
+    fn empty_or_overlong_request_ids_are_rejected() {
+        assert_err!(RequestId::parse(""));
+        assert_err!(RequestId::parse(&"a".repeat(129)));
+    }
+
+    #[test]
+    fn request_ids_with_spaces_or_control_characters_are_rejected() {
+        for id in ["abc def", "abc\r\nSet-Cookie: x", "id\"quoted\"", "ünïcode"] {
+            assert_err!(RequestId::parse(id), "{:?} was accepted", id);
+        }
+    }
+}
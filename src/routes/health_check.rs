@@ -1,5 +1,119 @@
-use actix_web::HttpResponse;
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
+use crate::migrations::migration_status;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
+/// Liveness: the process is up and serving requests.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+pub struct CheckReport {
+    pub status: CheckStatus,
+    pub latency_ms: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Readiness {
+    pub status: CheckStatus,
+    pub checks: BTreeMap<&'static str, CheckReport>,
+}
+
+/// Readiness: every dependency we need to serve traffic is reachable.
+///
+/// Returns a 503 if any check fails or does not complete in time;
+/// the reason is only logged.
+#[tracing::instrument(name = "Check readiness", skip(pool, email_client, settings))]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let email_provider = async {
+        if settings.check_email_provider {
+            Some(run_check("email_provider", timeout, email_client.ping()).await)
+        } else {
+            None
+        }
+    };
+    let (database, migrations, email_provider) = tokio::join!(
+        run_check("database", timeout, ping_database(&pool)),
+        run_check("migrations", timeout, check_migrations(&pool)),
+        email_provider,
+    );
+
+    let mut checks = BTreeMap::from([("database", database), ("migrations", migrations)]);
+    if let Some(email_provider) = email_provider {
+        checks.insert("email_provider", email_provider);
+    }
+    let status = if checks.values().all(|c| c.status == CheckStatus::Up) {
+        CheckStatus::Up
+    } else {
+        CheckStatus::Down
+    };
+    let readiness = Readiness { status, checks };
+    match status {
+        CheckStatus::Up => HttpResponse::Ok().json(readiness),
+        CheckStatus::Down => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+async fn run_check(
+    name: &'static str,
+    timeout: Duration,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(timeout, check).await {
+        Ok(outcome) => outcome,
+        Err(_) => Err(anyhow::anyhow!("Timed out after {:?}.", timeout)),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let status = match outcome {
+        Ok(()) => CheckStatus::Up,
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                check = name,
+                "A readiness check failed."
+            );
+            CheckStatus::Down
+        }
+    };
+    CheckReport { status, latency_ms }
+}
+
+async fn ping_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS ping")
+        .fetch_one(pool)
+        .await
+        .context("Failed to run a query against the database.")?;
+    Ok(())
+}
+
+/// Every migration embedded in this binary has been applied successfully.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let status = migration_status(pool).await?;
+    if !status.pending.is_empty() {
+        anyhow::bail!(
+            "{} migration(s) have not been applied.",
+            status.pending.len()
+        );
+    }
+    Ok(())
+}
@@ -1,9 +1,21 @@
+mod admin;
 mod health_check;
+mod login;
+mod metrics;
 mod newsletters;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_form_token;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
+pub use login::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_form_token::*;
+pub use subscriptions_unsubscribe::*;
@@ -0,0 +1,454 @@
+use super::publish::{insert_newsletter_issue, Content};
+use super::{authenticate, parse_content, parse_send_at, PublishError};
+use crate::domain::{IssueContent, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::email_templates::{sample_recipient, EmailTemplates, Recipient, RenderedEmail};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::request_id::RequestId;
+use crate::routes::unsubscribe_link;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// A test-send is for reviewers, not an audience
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+#[derive(serde::Deserialize)]
+pub struct DraftData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EditDraftData {
+    // The version the edit is based on
+    version: i32,
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VersionParameters {
+    // The latest version if left out
+    version: Option<i32>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    recipients: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftData {
+    // The version that was reviewed: publishing fails if it is no longer the latest
+    version: i32,
+    // Leave out to send the issue right away
+    send_at: Option<String>,
+}
+
+struct DraftVersion {
+    version: i32,
+    title: String,
+    text_content: String,
+    html_content: String,
+    markdown_content: Option<String>,
+    newsletter_issue_id: Option<Uuid>,
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter draft",
+    skip(body, pool, email_templates, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_draft(
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let content = parse_content(&email_templates, &body.title, &body.content)?;
+    let draft_id = Uuid::new_v4();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_drafts (draft_id, current_version, created_at)
+        VALUES ($1, 1, now())
+        "#,
+        draft_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store a new draft")?;
+    insert_draft_version(&mut transaction, draft_id, 1, &body.title, &content)
+        .await
+        .context("Failed to store the first version of a draft")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new draft.")?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "draft_id": draft_id,
+        "version": 1,
+    })))
+}
+
+#[tracing::instrument(
+    name = "Get a newsletter draft",
+    skip(parameters, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_draft(
+    draft_id: web::Path<Uuid>,
+    parameters: web::Query<VersionParameters>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let draft = get_draft_version(&pool, *draft_id, parameters.version).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "draft_id": *draft_id,
+        "version": draft.version,
+        "title": draft.title,
+        "content": {
+            "html": draft.html_content,
+            "text": draft.text_content,
+            "markdown": draft.markdown_content,
+        },
+        "newsletter_issue_id": draft.newsletter_issue_id,
+    })))
+}
+
+/// Store a new version of the draft.
+///
+/// Edits based on anything but the latest version are rejected:
+/// they would silently undo someone else's changes.
+#[tracing::instrument(
+    name = "Edit a newsletter draft",
+    skip(body, pool, email_templates, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn edit_draft(
+    draft_id: web::Path<Uuid>,
+    body: web::Json<EditDraftData>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let content = parse_content(&email_templates, &body.title, &body.content)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let current_version = lock_unpublished_draft(&mut transaction, *draft_id).await?;
+    if body.version != current_version {
+        return Err(stale_version(body.version, current_version));
+    }
+    let version = current_version + 1;
+    insert_draft_version(&mut transaction, *draft_id, version, &body.title, &content)
+        .await
+        .context("Failed to store a new version of a draft")?;
+    sqlx::query!(
+        r#"UPDATE newsletter_drafts SET current_version = $2 WHERE draft_id = $1"#,
+        *draft_id,
+        version
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the current version of a draft")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to edit a draft.")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "draft_id": *draft_id,
+        "version": version,
+    })))
+}
+
+/// The HTML body exactly as subscribers will receive it.
+#[tracing::instrument(
+    name = "Preview a newsletter draft",
+    skip(parameters, pool, email_templates, base_url, hmac_secret, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn preview_draft(
+    draft_id: web::Path<Uuid>,
+    parameters: web::Query<VersionParameters>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let draft = get_draft_version(&pool, *draft_id, parameters.version).await?;
+    let (name, email) = sample_recipient();
+    let recipient = Recipient {
+        name: &name,
+        email: &email,
+    };
+    let rendered = render_draft(&draft, &email_templates, recipient, &base_url, &hmac_secret)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(rendered.html))
+}
+
+/// Email the latest version of the draft to the given addresses only.
+#[tracing::instrument(
+    name = "Send a newsletter draft as a test",
+    skip(
+        body,
+        pool,
+        email_client,
+        email_templates,
+        base_url,
+        hmac_secret,
+        request,
+        request_id
+    ),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn test_send_draft(
+    draft_id: web::Path<Uuid>,
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+    request_id: RequestId,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let recipients = parse_test_recipients(&body.recipients)?;
+    let draft = get_draft_version(&pool, *draft_id, None).await?;
+    let subject = format!("[Test] {}", draft.title);
+    // Reviewers see the draft as the stand-in subscriber would
+    let (name, _) = sample_recipient();
+    for email in &recipients {
+        let recipient = Recipient { name: &name, email };
+        let rendered = render_draft(&draft, &email_templates, recipient, &base_url, &hmac_secret)?;
+        email_client
+            .send_email_with_headers(
+                email,
+                &subject,
+                &rendered.html,
+                &rendered.text,
+                &[request_id.email_header()],
+            )
+            .await
+            .with_context(|| format!("Failed to send a test email to {}", email.as_ref()))?;
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "version": draft.version,
+        "recipients": recipients.len(),
+    })))
+}
+
+/// Turn the reviewed version of the draft into a newsletter issue, sent
+/// right away or scheduled for `send_at`. A draft is published once.
+#[tracing::instrument(
+    name = "Publish a newsletter draft",
+    skip(body, pool, request, request_id),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn publish_draft(
+    draft_id: web::Path<Uuid>,
+    body: web::Json<PublishDraftData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    request_id: RequestId,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let send_at = body
+        .send_at
+        .as_deref()
+        .map(|send_at| parse_send_at(send_at, Utc::now()))
+        .transpose()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let current_version = lock_unpublished_draft(&mut transaction, *draft_id).await?;
+    if body.version != current_version {
+        return Err(stale_version(body.version, current_version));
+    }
+    let draft = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_draft_versions
+        WHERE draft_id = $1 AND version = $2
+        "#,
+        *draft_id,
+        current_version
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to retrieve the draft to publish")?;
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &draft.title,
+        &draft.text_content,
+        &draft.html_content,
+        send_at,
+        &request_id,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id, Some(request_id.as_str()))
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+    sqlx::query!(
+        r#"UPDATE newsletter_drafts SET newsletter_issue_id = $2 WHERE draft_id = $1"#,
+        *draft_id,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark a draft as published")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a draft.")?;
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "title": draft.title,
+        "send_at": send_at.map(|send_at| send_at.to_rfc3339()),
+    })))
+}
+
+fn parse_test_recipients(recipients: &[String]) -> Result<Vec<SubscriberEmail>, PublishError> {
+    if recipients.is_empty() || recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(PublishError::ValidationError(format!(
+            "A test can be sent to between 1 and {} recipients.",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    recipients
+        .iter()
+        .map(|recipient| SubscriberEmail::parse(recipient.clone()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(PublishError::ValidationError)
+}
+
+fn render_draft(
+    draft: &DraftVersion,
+    email_templates: &EmailTemplates,
+    recipient: Recipient<'_>,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<RenderedEmail, PublishError> {
+    // A stand-in subscriber: following the link unsubscribes nobody
+    let link = unsubscribe_link(&base_url.0, hmac_secret, Uuid::nil());
+    email_templates
+        .newsletter(
+            &draft.title,
+            &draft.html_content,
+            &draft.text_content,
+            recipient,
+            &link,
+        )
+        .map_err(|e| {
+            PublishError::ValidationError(format!("The draft is not a valid template: {:#}", e))
+        })
+}
+
+fn stale_version(version: i32, current_version: i32) -> PublishError {
+    PublishError::DraftConflict(format!(
+        "Version {} is not the latest version of the draft: reload version {}.",
+        version, current_version
+    ))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft_version(
+    pool: &PgPool,
+    draft_id: Uuid,
+    version: Option<i32>,
+) -> Result<DraftVersion, PublishError> {
+    sqlx::query_as!(
+        DraftVersion,
+        r#"
+        SELECT
+            v.version, v.title, v.text_content, v.html_content, v.markdown_content,
+            d.newsletter_issue_id
+        FROM newsletter_drafts d
+        JOIN newsletter_draft_versions v
+            ON v.draft_id = d.draft_id AND v.version = COALESCE($2, d.current_version)
+        WHERE d.draft_id = $1
+        "#,
+        draft_id,
+        version
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a draft")?
+    .ok_or(PublishError::UnknownDraft)
+}
+
+/// Lock the draft until the transaction ends and return its current version.
+#[tracing::instrument(skip(transaction))]
+async fn lock_unpublished_draft(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    draft_id: Uuid,
+) -> Result<i32, PublishError> {
+    let draft = sqlx::query!(
+        r#"
+        SELECT current_version, newsletter_issue_id
+        FROM newsletter_drafts
+        WHERE draft_id = $1
+        FOR UPDATE
+        "#,
+        draft_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a draft")?
+    .ok_or(PublishError::UnknownDraft)?;
+    if draft.newsletter_issue_id.is_some() {
+        return Err(PublishError::DraftConflict(
+            "The draft has already been published.".into(),
+        ));
+    }
+    Ok(draft.current_version)
+}
+
+#[tracing::instrument(skip(transaction, title, content))]
+async fn insert_draft_version(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    draft_id: Uuid,
+    version: i32,
+    title: &str,
+    content: &IssueContent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_draft_versions (
+            draft_id, version, title, text_content, html_content, markdown_content, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+        draft_id,
+        version,
+        title,
+        content.text(),
+        content.html(),
+        content.markdown()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! The newsletter API, for publishers authenticating with `Basic` credentials.
+mod drafts;
+mod publish;
+mod report;
+mod scheduled;
+
+pub use drafts::*;
+pub use publish::*;
+pub use report::*;
+pub use scheduled::*;
+
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::domain::IssueContent;
+use crate::email_templates::EmailTemplates;
+use crate::problem::Problem;
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use uuid::Uuid;
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    // New error variant!
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("There is no scheduled issue with the provided id.")]
+    UnknownScheduledIssue,
+    #[error("There is no newsletter issue with the provided id.")]
+    UnknownIssue,
+    #[error("There is no draft with the provided id.")]
+    UnknownDraft,
+    #[error("{0}")]
+    DraftConflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// Same logic to get the full error chain on `Debug`
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::ValidationError(e) => Problem::validation(e).into_response(),
+            PublishError::UnexpectedError(_) => Problem::internal().into_response(),
+            PublishError::UnknownScheduledIssue => Problem::new(
+                self.status_code(),
+                "unknown-scheduled-issue",
+                "Unknown scheduled issue.",
+            )
+            .with_detail(self.to_string())
+            .into_response(),
+            PublishError::UnknownIssue => {
+                Problem::new(self.status_code(), "unknown-issue", "Unknown issue.")
+                    .with_detail(self.to_string())
+                    .into_response()
+            }
+            PublishError::UnknownDraft => {
+                Problem::new(self.status_code(), "unknown-draft", "Unknown draft.")
+                    .with_detail(self.to_string())
+                    .into_response()
+            }
+            PublishError::DraftConflict(_) => Problem::new(
+                self.status_code(),
+                "draft-conflict",
+                "The draft has changed.",
+            )
+            .with_detail(self.to_string())
+            .into_response(),
+            PublishError::AuthError(_) => {
+                let mut response = Problem::new(
+                    self.status_code(),
+                    "authentication-failed",
+                    "Authentication failed.",
+                )
+                .into_response();
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    // actix_web::http::header provides a collection of constants
+                    // for the names of several well-known/standard HTTP headers
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            // Already dispatched and cancelled issues are gone as well
+            PublishError::UnknownScheduledIssue => StatusCode::NOT_FOUND,
+            PublishError::UnknownIssue => StatusCode::NOT_FOUND,
+            PublishError::UnknownDraft => StatusCode::NOT_FOUND,
+            // Edited by someone else, or already published
+            PublishError::DraftConflict(_) => StatusCode::CONFLICT,
+            // Return a 401 for auth errors
+            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// Check the `Basic` credentials of the request and return who sent it.
+async fn authenticate(request: &HttpRequest, pool: &PgPool) -> Result<Uuid, PublishError> {
+    let credentials = basic_authentication(request.headers())
+        // Bubble up the error, performing the necessary conversion
+        .map_err(PublishError::AuthError)?;
+
+    let user_id = validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+/// Derive both bodies of an issue from what was submitted.
+///
+/// They are templates: reject them now rather than when the issue is sent.
+fn parse_content(
+    email_templates: &EmailTemplates,
+    title: &str,
+    content: &Content,
+) -> Result<IssueContent, PublishError> {
+    let content = IssueContent::parse(
+        content.html.clone(),
+        content.text.clone(),
+        content.markdown.clone(),
+    )
+    .map_err(PublishError::ValidationError)?;
+    email_templates
+        .check_issue(title, content.html(), content.text())
+        .map_err(|e| {
+            PublishError::ValidationError(format!("The content is not a valid template: {}", e))
+        })?;
+    Ok(content)
+}
+
+/// `send_at` must say which timezone it is in, e.g. `2030-01-01T09:00:00+01:00`:
+/// editors and servers rarely agree on local time.
+fn parse_send_at(send_at: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, PublishError> {
+    let send_at = DateTime::parse_from_rfc3339(send_at).map_err(|_| {
+        PublishError::ValidationError(format!(
+            "{} is not a valid `send_at`: use an RFC 3339 timestamp with an explicit \
+            timezone, e.g. 2030-01-01T09:00:00+01:00.",
+            send_at
+        ))
+    })?;
+    let send_at = send_at.with_timezone(&Utc);
+    if send_at <= now {
+        return Err(PublishError::ValidationError(
+            "`send_at` must be in the future.".into(),
+        ));
+    }
+    Ok(send_at)
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // The header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimitator
+    let mut credentials = decoded_credentials.splitn(2, ':');
+
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials { username, password })
+}
@@ -0,0 +1,169 @@
+use super::{authenticate, parse_content, parse_send_at, PublishError};
+use crate::configuration::IdempotencySettings;
+use crate::email_templates::EmailTemplates;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::request_id::RequestId;
+use actix_web::http::header::HeaderMap;
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // Leave out to send the issue right away
+    send_at: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    pub(super) html: Option<String>,
+    pub(super) text: Option<String>,
+    // Rendered to whichever of `html` and `text` is left out
+    pub(super) markdown: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, idempotency, email_templates, request, request_id),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    idempotency: web::Data<IdempotencySettings>,
+    email_templates: web::Data<EmailTemplates>,
+    // New extractor!
+    request: HttpRequest,
+    request_id: RequestId,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate(&request, &pool).await?;
+    // Validate before claiming the idempotency key
+    let content = parse_content(&email_templates, &body.title, &body.content)?;
+    let send_at = body
+        .send_at
+        .as_deref()
+        .map(|send_at| parse_send_at(send_at, Utc::now()))
+        .transpose()?;
+
+    let idempotency_key = idempotency_key(request.headers())?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(&pool, idempotency_key, user_id, idempotency.expiration()).await? {
+                NextAction::StartProcessing(t) => t,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+            }
+        }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+
+    // Delivery is handled by the background worker in `issue_delivery_worker`:
+    // we only persist the issue and enqueue one task per confirmed subscriber.
+    // Scheduled issues are enqueued by `newsletter_scheduler` once they are due.
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        content.text(),
+        content.html(),
+        send_at,
+        &request_id,
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id, Some(request_id.as_str()))
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+    }
+    // The id is what `GET /newsletters/{issue_id}/report` needs
+    let response = HttpResponse::Accepted().json(serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "title": body.title,
+        "send_at": send_at.map(|send_at| send_at.to_rfc3339()),
+    }));
+
+    match idempotency_key {
+        // Saving the response commits the transaction as well
+        Some(idempotency_key) => {
+            let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+            Ok(response)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue.")?;
+            Ok(response)
+        }
+    }
+}
+
+/// Extract the optional `Idempotency-Key` header.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, PublishError> {
+    let header_value = match headers.get("Idempotency-Key") {
+        Some(header_value) => header_value,
+        None => return Ok(None),
+    };
+    let idempotency_key = header_value
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError(
+                "The 'Idempotency-Key' header was not a valid UTF8 string.".into(),
+            )
+        })?
+        .to_string()
+        .try_into()
+        .map_err(PublishError::ValidationError)?;
+    Ok(Some(idempotency_key))
+}
+
+#[tracing::instrument(skip_all)]
+pub(super) async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    send_at: Option<DateTime<Utc>>,
+    request_id: &RequestId,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    // A scheduled issue is published when the scheduler dispatches it
+    let (status, published_at) = match send_at {
+        None => ("published", Some(Utc::now())),
+        Some(_) => ("scheduled", None),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            published_at,
+            status,
+            send_at,
+            request_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        published_at,
+        status,
+        send_at,
+        request_id.as_str()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
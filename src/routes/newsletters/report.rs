@@ -0,0 +1,133 @@
+use super::{authenticate, PublishError};
+use crate::request_id::RequestId;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+struct FailedRecipient {
+    email: String,
+    attempts: i32,
+    last_error: Option<String>,
+}
+
+/// How far the delivery of an issue has got, and who did not receive it.
+#[tracing::instrument(
+    name = "Report on the delivery of a newsletter issue",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn delivery_report(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let issue = sqlx::query!(
+        r#"SELECT title, status FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        *issue_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to retrieve a newsletter issue")?
+    .ok_or(PublishError::UnknownIssue)?;
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE state = 'queued') as "queued!",
+            COUNT(*) FILTER (WHERE state = 'sent') as "sent!",
+            COUNT(*) FILTER (WHERE state = 'failed') as "failed!",
+            COUNT(*) FILTER (WHERE state = 'bounced') as "bounced!",
+            COUNT(*) FILTER (WHERE state = 'skipped') as "skipped!"
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        *issue_id
+    )
+    .fetch_one(pool.as_ref())
+    .await
+    .context("Failed to count the deliveries of a newsletter issue")?;
+    let failed_recipients = sqlx::query_as!(
+        FailedRecipient,
+        r#"
+        SELECT subscriber_email as email, attempts, last_error
+        FROM newsletter_deliveries
+        WHERE newsletter_issue_id = $1 AND state = 'failed'
+        ORDER BY subscriber_email
+        "#,
+        *issue_id
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve the failed deliveries of a newsletter issue")?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "newsletter_issue_id": *issue_id,
+        "title": issue.title,
+        "status": issue.status,
+        "deliveries": {
+            "queued": counts.queued,
+            "sent": counts.sent,
+            "failed": counts.failed,
+            // Nothing reports bounces yet
+            "bounced": counts.bounced,
+            // Recipients who unsubscribed before their email was sent
+            "skipped": counts.skipped,
+        },
+        "failed_recipients": failed_recipients,
+    })))
+}
+
+/// Enqueue the failed deliveries of an issue again: recipients who got
+/// the issue, bounced, or unsubscribed in the meantime, are left alone.
+#[tracing::instrument(
+    name = "Retry the failed deliveries of a newsletter issue",
+    skip(pool, request, request_id),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn retry_failed_deliveries(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    request_id: RequestId,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        *issue_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to retrieve a newsletter issue")?
+    .ok_or(PublishError::UnknownIssue)?;
+    // The rows are locked by the `UPDATE`: a concurrent retry waits,
+    // then finds nothing left to retry
+    let n_retried = sqlx::query!(
+        r#"
+        WITH retried AS (
+            UPDATE newsletter_deliveries
+            SET state = 'queued', updated_at = now()
+            WHERE newsletter_issue_id = $1 AND state = 'failed'
+            RETURNING subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, request_id)
+        SELECT $1, subscriber_email, $2 FROM retried
+        ON CONFLICT DO NOTHING
+        "#,
+        *issue_id,
+        request_id.as_str()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enqueue the failed deliveries again")?
+    .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to retry failed deliveries.")?;
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "retried": n_retried })))
+}
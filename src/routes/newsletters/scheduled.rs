@@ -0,0 +1,119 @@
+use super::{authenticate, parse_send_at, PublishError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    send_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    send_at: String,
+}
+
+impl ScheduledIssue {
+    fn new(newsletter_issue_id: Uuid, title: String, send_at: DateTime<Utc>) -> Self {
+        Self {
+            newsletter_issue_id,
+            title,
+            send_at: send_at.to_rfc3339(),
+        }
+    }
+}
+
+/// The issues waiting to be dispatched, the next one first.
+#[tracing::instrument(
+    name = "List scheduled newsletter issues",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_scheduled_issues(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, send_at as "send_at!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve scheduled newsletter issues")?
+    .into_iter()
+    .map(|r| ScheduledIssue::new(r.newsletter_issue_id, r.title, r.send_at))
+    .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+/// Move an issue to a new `send_at`, as long as it has not been dispatched yet.
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn reschedule_issue(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let send_at = parse_send_at(&body.send_at, Utc::now())?;
+    // The scheduler locks due issues while dispatching them:
+    // this waits for it, then finds the issue already published
+    let issue = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        RETURNING title
+        "#,
+        *issue_id,
+        send_at
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to reschedule a newsletter issue")?
+    .ok_or(PublishError::UnknownScheduledIssue)?;
+    Ok(HttpResponse::Ok().json(ScheduledIssue::new(*issue_id, issue.title, send_at)))
+}
+
+/// Make sure a scheduled issue is never sent.
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn cancel_scheduled_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    authenticate(&request, &pool).await?;
+    let n_cancelled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        *issue_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to cancel a scheduled newsletter issue")?
+    .rows_affected();
+    if n_cancelled == 0 {
+        return Err(PublishError::UnknownScheduledIssue);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,181 @@
+use crate::authentication::change_password;
+use crate::domain::NewPassword;
+use crate::routes::password_reset::hash_reset_token;
+use crate::session_store::delete_user_sessions;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    reset_token: String,
+}
+
+const INVALID_LINK_MESSAGE: &str =
+    "The password reset link is invalid or has expired. Please ask for a new one.";
+
+pub async fn reset_password_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    // Check the token before echoing it back in the form
+    if get_user_id_from_token(&mut transaction, &parameters.reset_token)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error(INVALID_LINK_MESSAGE).send();
+        return Ok(see_other("/password-reset"));
+    }
+
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(messages_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset password</title>
+</head>
+<body>
+    {messages_html}
+    <form action="/password-reset/confirm" method="post">
+        <input type="hidden" name="reset_token" value="{}">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+            parameters.reset_token
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    reset_token: String,
+    new_password: String,
+    new_password_check: String,
+}
+
+#[tracing::instrument(name = "Reset password", skip(form, pool))]
+pub async fn reset_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let user_id = match get_user_id_from_token(&mut transaction, &form.reset_token)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error(INVALID_LINK_MESSAGE).send();
+            return Ok(see_other("/password-reset"));
+        }
+    };
+    // The token is known to be alphanumeric: it is safe to put it back in a URL
+    let retry_location = format!("/password-reset/confirm?reset_token={}", form.reset_token);
+    if form.new_password != form.new_password_check {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&retry_location));
+    }
+    let new_password = match NewPassword::parse(form.0.new_password) {
+        Ok(new_password) => new_password,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&retry_location));
+        }
+    };
+
+    change_password(user_id, new_password, &mut transaction)
+        .await
+        .map_err(e500)?;
+    // Links are single-use: any other link sent to the user is voided as well
+    delete_user_tokens(&mut transaction, user_id)
+        .await
+        .map_err(e500)?;
+    // Whoever knew the old password must not stay logged in
+    delete_user_sessions(&mut transaction, user_id)
+        .await
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to consume a password reset token.")
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been reset. You can now log in.").send();
+    Ok(see_other("/login"))
+}
+
+#[tracing::instrument(name = "Get user_id from reset token", skip(transaction, reset_token))]
+async fn get_user_id_from_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    reset_token: &str,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    // `FOR UPDATE`: two concurrent submissions cannot both use the token
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens
+        WHERE password_reset_token_hash = $1 AND expires_at > now()
+        FOR UPDATE
+        "#,
+        hash_reset_token(reset_token),
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to retrieve a password reset token.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+#[tracing::instrument(name = "Delete password reset tokens", skip(transaction))]
+async fn delete_user_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete password reset tokens.")?;
+    Ok(())
+}
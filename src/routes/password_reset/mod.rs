@@ -0,0 +1,26 @@
+//! "Forgot password" flow: a time-limited, single-use link is emailed to
+//! the address stored in `users.email`.
+mod confirm;
+mod request;
+
+pub use confirm::{reset_password, reset_password_form};
+pub use request::{request_password_reset, request_password_reset_form};
+
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha3::Digest;
+
+/// Generate a random 32-characters-long case-sensitive reset token.
+fn generate_reset_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+/// Only the digest of a token is stored, like for subscription tokens.
+fn hash_reset_token(reset_token: &str) -> String {
+    let hash = sha3::Sha3_256::digest(reset_token.as_bytes());
+    format!("{:x}", hash)
+}
@@ -0,0 +1,194 @@
+use crate::configuration::PasswordResetSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::request_id::RequestId;
+use crate::routes::password_reset::{generate_reset_token, hash_reset_token};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub async fn request_password_reset_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(messages_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot password</title>
+</head>
+<body>
+    {messages_html}
+    <form action="/password-reset" method="post">
+        <label>Email
+            <input
+                type="email"
+                placeholder="Enter the email address of your account"
+                name="email"
+            >
+        </label>
+        <button type="submit">Send me a reset link</button>
+    </form>
+    <p><a href="/login">&lt;- Back to login</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, base_url, settings, request_id)
+)]
+pub async fn request_password_reset(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<PasswordResetSettings>,
+    request_id: RequestId,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The answer is the same whether or not we know the address:
+    // this form must not tell who has an account.
+    let done = || {
+        FlashMessage::info(
+            "If that address belongs to an account, \
+            you will shortly receive a link to reset your password.",
+        )
+        .send();
+        see_other("/password-reset")
+    };
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => return Ok(done()),
+    };
+    let user_id = match get_user_id_by_email(&pool, &email).await.map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(done()),
+    };
+
+    let reset_token = generate_reset_token();
+    store_reset_token(
+        &pool,
+        user_id,
+        &reset_token,
+        Utc::now() + settings.expiration(),
+    )
+    .await
+    .map_err(e500)?;
+    send_password_reset_email(
+        email_client.as_ref(),
+        &email,
+        &base_url.0,
+        &reset_token,
+        &settings,
+        &request_id,
+    )
+    .await
+    .context("Failed to send a password reset email.")
+    .map_err(e500)?;
+    Ok(done())
+}
+
+#[tracing::instrument(name = "Get user_id from email", skip(pool, email))]
+async fn get_user_id_by_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE email = $1 AND disabled_at IS NULL"#,
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a user by email.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+#[tracing::instrument(name = "Store password reset token", skip(pool, reset_token))]
+async fn store_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    reset_token: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    // Piggyback on the insert to purge the links nobody used
+    sqlx::query!(r#"DELETE FROM password_reset_tokens WHERE expires_at < now()"#)
+        .execute(pool)
+        .await
+        .context("Failed to purge expired password reset tokens.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (
+            password_reset_token_hash,
+            user_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, now(), $3)
+        "#,
+        hash_reset_token(reset_token),
+        user_id,
+        expires_at,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a password reset email",
+    skip(email_client, recipient, reset_token, settings, request_id)
+)]
+async fn send_password_reset_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    reset_token: &str,
+    settings: &PasswordResetSettings,
+    request_id: &RequestId,
+) -> Result<(), SendEmailError> {
+    let reset_link = format!(
+        "{}/password-reset/confirm?reset_token={}",
+        base_url, reset_token
+    );
+    let minutes = settings.expiration().num_minutes();
+    let plain_body = format!(
+        "Visit {} to choose a new password.\n\
+        The link expires in {} minutes.\n\
+        If you did not ask for a password reset, you can ignore this email.",
+        reset_link, minutes
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to choose a new password.<br />\
+        The link expires in {} minutes.<br />\
+        If you did not ask for a password reset, you can ignore this email.",
+        reset_link, minutes
+    );
+    email_client
+        .send_email_with_headers(
+            recipient,
+            "Reset your password",
+            &html_body,
+            &plain_body,
+            &[request_id.email_header()],
+        )
+        .await?;
+    Ok(())
+}
@@ -1,16 +1,24 @@
 // use std::fmt::{Display, Formatter};
 use actix_web::{web, HttpResponse};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Postgres, Transaction};
 // use tracing_futures::Instrument;
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::bot_protection::{BotCheckError, BotProtection, Submission};
+use crate::configuration::SubscriptionTokenSettings;
+use crate::domain::{EmailPolicy, NewSubscriber, SubscriberEmail, SubscriberName};
 use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::email_templates::{EmailTemplates, Recipient};
+use crate::metrics::Metrics;
+use crate::problem::Problem;
+use crate::request_id::RequestId;
+use crate::routes::delete_subscriber_tokens;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
 use actix_web::http::StatusCode;
 use actix_web::ResponseError;
 use anyhow::Context;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use sha3::Digest;
 use uuid::Uuid;
 
 /// Generate a random 25-characters-long case-sensitive subscription token.
@@ -22,10 +30,33 @@ fn generate_subscription_token() -> String {
         .collect()
 }
 
+/// Only the digest of a token is stored: a database dump does not
+/// hand out working confirmation links.
+pub fn hash_subscription_token(subscription_token: &str) -> String {
+    let hash = sha3::Sha3_256::digest(subscription_token.as_bytes());
+    format!("{:x}", hash)
+}
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    // The honeypot: the form hides it from humans
+    #[serde(default)]
+    website: String,
+    // From `GET /subscriptions/form-token`
+    form_token: Option<String>,
+    challenge_response: Option<String>,
+}
+
+impl FormData {
+    fn submission(&self) -> Submission<'_> {
+        Submission {
+            website: &self.website,
+            form_token: self.form_token.as_deref(),
+            challenge_response: self.challenge_response.as_deref(),
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -59,53 +90,127 @@ impl ResponseError for SubscribeError {
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError(e) => Problem::validation(e),
+            SubscribeError::UnexpectedError(_) => Problem::internal(),
+        }
+        .into_response()
+    }
 }
 
 // form => urlencoding => Deserialize
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(
+        form,
+        pool,
+        email_client,
+        email_templates,
+        base_url,
+        token_settings,
+        metrics,
+        request_id,
+        bot_protection,
+        hmac_secret,
+        email_policy
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
     // Get the email client from the app context
     email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
     base_url: web::Data<ApplicationBaseUrl>,
+    token_settings: web::Data<SubscriptionTokenSettings>,
+    metrics: web::Data<Metrics>,
+    request_id: RequestId,
+    bot_protection: web::Data<BotProtection>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_policy: web::Data<EmailPolicy>,
 ) -> Result<HttpResponse, SubscribeError> {
+    bot_protection
+        .check(&form.submission(), &hmac_secret, Utc::now())
+        .await
+        .map_err(|e| match e {
+            BotCheckError::VerifierUnavailable(_) => SubscribeError::UnexpectedError(e.into()),
+            _ => SubscribeError::ValidationError(e.to_string()),
+        })?;
     // `web::Form` is a wrapper around `FormData`
     // `form.0` gives us access to the underlying `FormData`
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    email_policy
+        .check(&new_subscriber.email)
+        .map_err(|e| SubscribeError::ValidationError(e.to_string()))?;
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    // Inserting first: two concurrent sign-ups for a new address cannot both
+    // find no row and then both try to insert one.
+    let inserted_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
+    let is_new_subscriber = inserted_id.is_some();
+    let subscriber_id = match inserted_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let existing = get_existing_subscriber(&mut transaction, &new_subscriber.email)
+                .await
+                .context("Failed to look up an existing subscriber in the database.")?
+                .context("The subscriber we conflicted with does not exist.")?;
+            match existing.status.as_str() {
+                // Same response as for a brand new subscriber: we do not want
+                // to leak who is already on the list.
+                "confirmed" => return Ok(HttpResponse::Ok().finish()),
+                "unsubscribed" => {
+                    restart_double_opt_in(&mut transaction, existing.id)
+                        .await
+                        .context("Failed to reset an unsubscribed subscriber.")?;
+                    existing.id
+                }
+                // Still `pending_confirmation`: send them a fresh link
+                _ => existing.id,
+            }
+        }
+    };
 
     let subscriber_token = generate_subscription_token();
 
-    store_token(&mut transaction, subscriber_id, &subscriber_token)
-        .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        &subscriber_token,
+        Utc::now() + token_settings.expiration(),
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
+    if is_new_subscriber {
+        metrics.subscription_created();
+    }
 
     send_confirmation_email(
-        &email_client,
+        email_client.as_ref(),
+        &email_templates,
         new_subscriber,
         &base_url.0,
         &subscriber_token,
+        &request_id,
     )
     .await
     .context("Failed to send a confirmation email.")?;
@@ -115,33 +220,39 @@ pub async fn subscribe(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber)
+    skip(email_client, email_templates, new_subscriber, request_id)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
+    email_templates: &EmailTemplates,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    request_id: &RequestId,
+) -> Result<(), anyhow::Error> {
     // Build a confirmation link with a dynamic root
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
+    let recipient = Recipient {
+        name: &new_subscriber.name,
+        email: &new_subscriber.email,
+    };
+    let email = email_templates.confirmation(recipient, &confirmation_link)?;
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email_with_headers(
+            &new_subscriber.email,
+            "Welcome!",
+            &email.html,
+            &email.text,
+            &[request_id.email_header()],
+        )
+        .await?;
+    Ok(())
 }
 
+/// `None` if there already is a subscriber with the same email.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -149,19 +260,21 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -171,7 +284,49 @@ pub async fn insert_subscriber(
         // We will talk about error handling in depth later!
     })?;
 
-    Ok(subscriber_id)
+    Ok(inserted.map(|r| r.id))
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub status: String,
+}
+
+#[tracing::instrument(
+    name = "Looking up an existing subscriber by email",
+    skip(email, transaction)
+)]
+pub async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    // The row exists: `insert_subscriber` just conflicted with it.
+    // `FOR UPDATE` serialises concurrent changes to its status.
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref(),
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Restarting double opt-in for an unsubscribed subscriber",
+    skip(transaction)
+)]
+pub async fn restart_double_opt_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'pending_confirmation' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Links from the original subscription must not skip the new opt-in
+    delete_subscriber_tokens(transaction, subscriber_id).await
 }
 
 #[tracing::instrument(
@@ -182,12 +337,19 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    expires_at: DateTime<Utc>,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
-        subscription_token,
-        subscriber_id
+        r#"INSERT INTO subscription_tokens (
+            subscription_token_hash,
+            subscriber_id,
+            created_at,
+            expires_at
+        )
+        VALUES ($1, $2, now(), $3)"#,
+        hash_subscription_token(subscription_token),
+        subscriber_id,
+        expires_at,
     )
     .execute(transaction)
     .await
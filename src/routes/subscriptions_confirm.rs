@@ -59,11 +59,14 @@
 //
 //     Ok(result.map(|r| r.subscriber_id))
 // }
-use crate::routes::error_chain_fmt;
+use crate::metrics::Metrics;
+use crate::problem::Problem;
+use crate::routes::{error_chain_fmt, hash_subscription_token};
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -77,6 +80,8 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token.")]
     UnknownToken,
+    #[error("The provided token has expired.")]
+    ExpiredToken,
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -89,47 +94,111 @@ impl ResponseError for ConfirmationError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::UnknownToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
             Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::UnknownToken => {
+                Problem::new(self.status_code(), "unknown-token", "Unknown token.")
+                    .with_detail(self.to_string())
+            }
+            Self::ExpiredToken => {
+                Problem::new(self.status_code(), "expired-token", "Expired token.")
+                    .with_detail(self.to_string())
+            }
+            Self::UnexpectedError(_) => Problem::internal(),
+        }
+        .into_response()
+    }
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool, metrics))]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let token = get_token(&mut transaction, &parameters.subscription_token)
         .await
         .context("Failed to retrieve the subscriber id associated with the provided token.")?
         .ok_or(ConfirmationError::UnknownToken)?;
-    confirm_subscriber(&pool, subscriber_id)
+    if token.expires_at < Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    confirm_subscriber(&mut transaction, token.subscriber_id)
         .await
         .context("Failed to update the subscriber status to `confirmed`.")?;
+    // Tokens are single-use: the link cannot be replayed once it worked
+    delete_subscriber_tokens(&mut transaction, token.subscriber_id)
+        .await
+        .context("Failed to delete the tokens of a confirmed subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    metrics.subscription_confirmed();
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id,
     )
-    .execute(pool)
+    .execute(transaction)
     .await?;
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+pub struct SubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, transaction))]
+pub async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    // `FOR UPDATE`: two concurrent clicks cannot both consume the token
+    sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscriber_id, expires_at
+        FROM subscription_tokens
+        WHERE subscription_token_hash = $1
+        FOR UPDATE
+        "#,
+        hash_subscription_token(subscription_token),
+    )
+    .fetch_optional(transaction)
+    .await
+}
+
+#[tracing::instrument(name = "Delete subscription tokens", skip(subscriber_id, transaction))]
+pub async fn delete_subscriber_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
     )
-    .fetch_optional(pool)
+    .execute(transaction)
     .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(())
 }
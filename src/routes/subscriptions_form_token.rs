@@ -0,0 +1,23 @@
+use crate::bot_protection::BotProtection;
+use crate::startup::HmacSecret;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+
+#[derive(serde::Serialize)]
+struct FormToken {
+    form_token: String,
+}
+
+/// Issue the token the subscription form submits along with `email` and `name`.
+pub async fn subscription_form_token(
+    bot_protection: web::Data<BotProtection>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        // Each visitor gets a fresh one
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(FormToken {
+            form_token: bot_protection.issue_form_token(&hmac_secret, Utc::now()),
+        })
+}
@@ -0,0 +1,140 @@
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLink,
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Build the signed link a subscriber can follow to leave the list.
+///
+/// The same URL is advertised in the `List-Unsubscribe` header: mail clients
+/// supporting RFC 8058 will `POST` to it directly.
+pub fn unsubscribe_link(base_url: &str, hmac_secret: &HmacSecret, subscriber_id: Uuid) -> String {
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url,
+        subscriber_id,
+        sign(hmac_secret, subscriber_id)
+    )
+}
+
+fn mac(hmac_secret: &HmacSecret, subscriber_id: Uuid) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&hmac_secret.key_for("unsubscribe-links"))
+        .expect("HMAC can take a key of any size.");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+fn sign(hmac_secret: &HmacSecret, subscriber_id: Uuid) -> String {
+    hex::encode(mac(hmac_secret, subscriber_id).finalize().into_bytes())
+}
+
+fn verify(
+    hmac_secret: &HmacSecret,
+    parameters: &UnsubscribeParameters,
+) -> Result<(), UnsubscribeError> {
+    let tag = hex::decode(&parameters.token).map_err(|_| UnsubscribeError::InvalidLink)?;
+    // `verify_slice` compares in constant time
+    mac(hmac_secret, parameters.subscriber_id)
+        .verify_slice(&tag)
+        .map_err(|_| UnsubscribeError::InvalidLink)
+}
+
+/// Ask for a confirmation instead of unsubscribing on `GET`:
+/// link scanners and prefetchers must not be able to unsubscribe anybody.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify(&hmac_secret, &parameters)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you really want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?subscriber_id={}&token={}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            parameters.subscriber_id, parameters.token
+        )))
+}
+
+/// One-click unsubscribe (RFC 8058).
+///
+/// The body (`List-Unsubscribe=One-Click`) carries no information:
+/// the signed query parameters are all we need.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify(&hmac_secret, &parameters)?;
+    mark_subscriber_as_unsubscribed(&pool, parameters.subscriber_id)
+        .await
+        .context("Failed to update the subscriber status to `unsubscribed`.")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>You have been unsubscribed.</p>"))
+}
+
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(subscriber_id, pool))]
+pub async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use crate::session_state::TypedSession;
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+type SessionState = HashMap<String, String>;
+
+/// Keeps session state in the `sessions` table: the cookie only carries
+/// the (signed) session key.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Generate a random 64-characters-long session key.
+fn generate_session_key() -> SessionKey {
+    let mut rng = thread_rng();
+    let key: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+    key.try_into()
+        .expect("A 64-characters-long alphanumeric string is a valid session key.")
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    #[tracing::instrument(name = "Load session state", skip_all)]
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state.")
+        .map_err(LoadError::Other)?;
+        match row {
+            None => Ok(None),
+            Some(row) => serde_json::from_str(&row.state)
+                .context("Failed to deserialize the session state.")
+                .map(Some)
+                .map_err(LoadError::Deserialization),
+        }
+    }
+
+    #[tracing::instrument(name = "Save session state", skip_all)]
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        // New sessions are rare (one per login): a good time to purge stale ones
+        sqlx::query!(r#"DELETE FROM sessions WHERE expires_at < now()"#)
+            .execute(&self.pool)
+            .await
+            .context("Failed to purge expired sessions.")
+            .map_err(SaveError::Other)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state.")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    #[tracing::instrument(name = "Update session state", skip_all)]
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(UpdateError::Serialization)?;
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = $3
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state.")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+        if n_updated == 0 {
+            // The session expired (or was deleted) in the meantime:
+            // start a new one rather than resurrecting the old key.
+            return self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            });
+        }
+        Ok(session_key)
+    }
+
+    #[tracing::instrument(name = "Extend session", skip_all)]
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE sessions SET expires_at = $2 WHERE session_key = $1"#,
+            session_key.as_ref(),
+            expires_at(ttl),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to extend the session.")?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Delete session", skip_all)]
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref(),
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
+
+/// Log a user out everywhere, e.g. once their password has been reset.
+#[tracing::instrument(name = "Delete the sessions of a user", skip(transaction))]
+pub async fn delete_user_sessions(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    // Session values are JSON-encoded on their own, then stored in a JSON map
+    let encoded_user_id =
+        serde_json::to_string(&user_id).context("Failed to serialize a user id.")?;
+    sqlx::query!(
+        r#"DELETE FROM sessions WHERE state::jsonb ->> $1 = $2"#,
+        TypedSession::USER_ID_KEY,
+        encoded_user_id,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete the sessions of a user.")?;
+    Ok(())
+}
@@ -1,40 +1,83 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::authentication::reject_anonymous_users;
+use crate::configuration::Settings;
+use crate::email_client::{EmailClient, MeteredTransport};
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::metrics::{track_http_metrics, Metrics};
+use crate::migrations::{migration_status, run_migrations};
+use crate::newsletter_scheduler::run_scheduler_until_stopped;
+use crate::problem::attach_request_id;
+use crate::rate_limit::{limit_subscriptions, SubscriptionRateLimits};
+use crate::request_id::{propagate_request_id, RequestIdRootSpanBuilder};
+use crate::session_store::PgSessionStore;
+use crate::subscription_token_cleanup::run_cleanup_until_stopped;
 use actix_cors::Cors;
+use actix_session::config::CookieContentSecurity;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::{Key, SameSite};
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
 use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
+use sqlx::{ConnectOptions, PgPool};
 use std::net::TcpListener;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
 
 use crate::routes;
-use crate::routes::publish_newsletter;
+use crate::routes::{
+    cancel_scheduled_issue, create_draft, delivery_report, edit_draft, get_draft,
+    list_scheduled_issues, preview_draft, publish_draft, publish_newsletter, reschedule_issue,
+    retry_failed_deliveries, test_send_draft,
+};
 
 // A new type to hold the newly built server and its port
 pub struct Application {
     port: u16,
     server: Server,
+    // The newsletter delivery worker runs next to the HTTP server,
+    // sharing its connection pool.
+    connection_pool: PgPool,
+    email_client: Arc<EmailClient>,
+    email_templates: Arc<EmailTemplates>,
+    base_url: String,
+    hmac_secret: String,
+    token_cleanup_interval: std::time::Duration,
+    scheduler_interval: std::time::Duration,
 }
 
 impl Application {
     // We have converted the `build` function into a constructor for
     // `Application`.
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
-        let connection_pool = get_connection_pool(&configuration.database);
-
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
+        // Broken templates are reported now rather than when sending
+        let email_templates = Arc::new(
+            configuration
+                .email_templates
+                .templates()
+                .map_err(std::io::Error::other)?,
         );
+        let connection_pool = get_connection_pool(&configuration);
+        if configuration.database.migrate_on_startup {
+            run_migrations(&connection_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        } else {
+            // An older release must not write to a schema it does not know
+            migration_status(&connection_pool)
+                .await
+                .and_then(|status| status.ensure_compatible())
+                .map_err(std::io::Error::other)?;
+        }
+        let metrics = Metrics::new().map_err(std::io::Error::other)?;
+        let email_client: Arc<EmailClient> = Arc::new(MeteredTransport::new(
+            configuration.email_client.clone().client(),
+            metrics.clone(),
+        ));
 
         // We have removed the hard-coded `8000` - it's now coming from our settings!
         let address = format!(
@@ -45,13 +88,25 @@ impl Application {
         let port = listener.local_addr().unwrap().port();
         let server = run(
             listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
+            connection_pool.clone(),
+            email_client.clone(),
+            email_templates.clone(),
+            metrics,
+            &configuration,
         )?;
 
         // We "save" the bound port in one of `Application`'s fields
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            connection_pool,
+            email_client,
+            email_templates,
+            base_url: configuration.application.base_url,
+            hmac_secret: configuration.application.hmac_secret,
+            token_cleanup_interval: configuration.subscription_tokens.cleanup_interval(),
+            scheduler_interval: configuration.newsletter_scheduler.poll_interval(),
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -61,14 +116,42 @@ impl Application {
     // A more expressive name that makes it clear that
     // this function only returns when the application is stopped.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let token_cleanup =
+            run_cleanup_until_stopped(self.connection_pool.clone(), self.token_cleanup_interval);
+        let scheduler =
+            run_scheduler_until_stopped(self.connection_pool.clone(), self.scheduler_interval);
+        let worker = run_worker_until_stopped(
+            self.connection_pool,
+            self.email_client,
+            self.email_templates,
+            ApplicationBaseUrl(self.base_url),
+            HmacSecret(self.hmac_secret),
+        );
+        // Whichever stops first brings the whole application down with it.
+        tokio::select! {
+            outcome = self.server => outcome,
+            outcome = worker => {
+                outcome.map_err(std::io::Error::other)
+            }
+            outcome = token_cleanup => {
+                outcome.map_err(std::io::Error::other)
+            }
+            outcome = scheduler => {
+                outcome.map_err(std::io::Error::other)
+            }
+        }
     }
 }
 
-pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+pub fn get_connection_pool(configuration: &Settings) -> PgPool {
+    let mut options = configuration.database.with_db();
+    // sqlx logs bound parameters as they are: they would not go through the redactor
+    if configuration.telemetry.redaction.enabled {
+        options.disable_statement_logging();
+    }
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
-        .connect_lazy_with(configuration.with_db())
+        .connect_lazy_with(options)
 }
 
 // We need to define a wrapper type in order to retrieve the URL
@@ -77,19 +160,71 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 // a raw `String` would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
 
+// The secret the keys we sign with are derived from: the links we email
+// out (e.g. one-click unsubscribe links), form tokens, cookies.
+pub struct HmacSecret(pub String);
+
+impl HmacSecret {
+    /// The key for one `purpose` only: a signature made for a purpose
+    /// is worthless for any other.
+    pub fn key_for(&self, purpose: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(purpose.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Signs and encrypts the session and flash message cookies.
+    pub fn cookie_key(&self) -> Key {
+        // `Key` wants 64 bytes: one half signs, the other encrypts
+        let mut mac = Hmac::<Sha512>::new_from_slice(self.0.as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(b"cookies");
+        Key::from(&mac.finalize().into_bytes())
+    }
+}
+
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    // New parameter!
-    base_url: String,
+    email_client: Arc<EmailClient>,
+    email_templates: Arc<EmailTemplates>,
+    metrics: Metrics,
+    // Route handlers pick what they need from here via `web::Data`
+    configuration: &Settings,
 ) -> Result<Server, std::io::Error> {
     // Wrap the connection in a smart pointer
+    let session_store = PgSessionStore::new(db_pool.clone());
     let db_pool = web::Data::new(db_pool);
     // Capture `connection` from the surrounding environment
-    let email_client = web::Data::new(email_client);
+    let email_client = web::Data::from(email_client);
+    let email_templates = web::Data::from(email_templates);
+    let metrics = web::Data::new(metrics);
     // confirm email base url
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(
+        configuration.application.base_url.clone(),
+    ));
+    let hmac_secret = web::Data::new(HmacSecret(configuration.application.hmac_secret.clone()));
+    let secret_key = hmac_secret.cookie_key();
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
+    let idempotency = web::Data::new(configuration.idempotency.clone());
+    let subscription_tokens = web::Data::new(configuration.subscription_tokens.clone());
+    let password_reset = web::Data::new(configuration.password_reset.clone());
+    let health = web::Data::new(configuration.health.clone());
+    let email_policy = web::Data::new(
+        configuration
+            .email_policy
+            .policy()
+            .map_err(std::io::Error::other)?,
+    );
+    let bot_protection = web::Data::new(configuration.bot_protection.bot_protection());
+    let rate_limits = web::Data::new(SubscriptionRateLimits {
+        store: configuration.rate_limit.store(db_pool.get_ref().clone()),
+        trusted_proxies: configuration.rate_limit.trusted_proxies.clone(),
+        per_ip: configuration.rate_limit.per_ip,
+        per_email: configuration.rate_limit.per_email,
+    });
 
     let server = HttpServer::new(move || {
         let cors = Cors::default()
@@ -107,20 +242,136 @@ pub fn run(
 
         App::new()
             .wrap(cors)
+            .wrap(message_framework.clone())
+            // Only the session key travels in the cookie, signed and out of reach of scripts
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .cookie_http_only(true)
+                    .cookie_same_site(SameSite::Strict)
+                    .build(),
+            )
+            .wrap(from_fn(attach_request_id))
             // Middlewares are added using the `wrap` method on `App`
-            .wrap(TracingLogger::default())
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(from_fn(propagate_request_id))
+            .wrap(from_fn(track_http_metrics))
             .route("/health_check", web::get().to(routes::health_check))
-            .route("/subscriptions", web::post().to(routes::subscribe))
+            .route("/health/ready", web::get().to(routes::health_ready))
+            .route("/metrics", web::get().to(routes::export_metrics))
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
+            .route(
+                "/password-reset",
+                web::get().to(routes::request_password_reset_form),
+            )
+            .route(
+                "/password-reset",
+                web::post().to(routes::request_password_reset),
+            )
+            .route(
+                "/password-reset/confirm",
+                web::get().to(routes::reset_password_form),
+            )
+            .route(
+                "/password-reset/confirm",
+                web::post().to(routes::reset_password),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route("/password", web::get().to(routes::change_password_form))
+                    .route("/password", web::post().to(routes::change_password))
+                    .route("/logout", web::post().to(routes::log_out)),
+            )
+            .route(
+                "/subscriptions",
+                web::post()
+                    .to(routes::subscribe)
+                    .wrap(from_fn(limit_subscriptions)),
+            )
+            .route(
+                "/subscriptions/form-token",
+                web::get().to(routes::subscription_form_token),
+            )
             .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(routes::unsubscribe_form),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(routes::unsubscribe),
+            )
             // Register the new handler!
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route(
+                "/newsletters/{issue_id}/report",
+                web::get().to(delivery_report),
+            )
+            .route(
+                "/newsletters/{issue_id}/retry-failed",
+                web::post().to(retry_failed_deliveries),
+            )
+            .route("/newsletters/drafts", web::post().to(create_draft))
+            .route("/newsletters/drafts/{draft_id}", web::get().to(get_draft))
+            .route("/newsletters/drafts/{draft_id}", web::put().to(edit_draft))
+            .route(
+                "/newsletters/drafts/{draft_id}/preview",
+                web::get().to(preview_draft),
+            )
+            .route(
+                "/newsletters/drafts/{draft_id}/test-send",
+                web::post().to(test_send_draft),
+            )
+            .route(
+                "/newsletters/drafts/{draft_id}/publish",
+                web::post().to(publish_draft),
+            )
+            .route(
+                "/newsletters/scheduled",
+                web::get().to(list_scheduled_issues),
+            )
+            .route(
+                "/newsletters/scheduled/{issue_id}",
+                web::put().to(reschedule_issue),
+            )
+            .route(
+                "/newsletters/scheduled/{issue_id}",
+                web::delete().to(cancel_scheduled_issue),
+            )
             // Get a pointer copy and attach it to the application state
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(idempotency.clone())
+            .app_data(subscription_tokens.clone())
+            .app_data(password_reset.clone())
+            .app_data(metrics.clone())
+            .app_data(health.clone())
+            .app_data(rate_limits.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_policy.clone())
     })
     .listen(listener)?
     .run();
 
     Ok(server)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HmacSecret;
+
+    #[test]
+    fn each_purpose_gets_its_own_key() {
+        let secret = HmacSecret("a-secret".into());
+
+        assert_ne!(secret.key_for("one"), secret.key_for("another"));
+        assert_ne!(secret.key_for("one"), b"a-secret".to_vec());
+        assert_eq!(secret.key_for("one"), secret.key_for("one"));
+    }
+}
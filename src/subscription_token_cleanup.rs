@@ -0,0 +1,30 @@
+use crate::rate_limit::delete_full_buckets;
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Purge expired rows from `subscription_tokens`, and refilled buckets
+/// from `rate_limit_buckets`, every `interval` until the process is stopped.
+///
+/// It is safe for several replicas to run this loop at the same time.
+pub async fn run_cleanup_until_stopped(
+    pool: PgPool,
+    interval: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by `delete_expired_tokens` and `delete_full_buckets`:
+        // we try again next time.
+        let _ = delete_expired_tokens(&pool).await;
+        let _ = delete_full_buckets(&pool).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[tracing::instrument(skip_all, fields(n_deleted=tracing::field::Empty), err)]
+pub async fn delete_expired_tokens(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(r#"DELETE FROM subscription_tokens WHERE expires_at < now()"#)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    tracing::Span::current().record("n_deleted", n_deleted);
+    Ok(n_deleted)
+}
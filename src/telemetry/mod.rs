@@ -0,0 +1,278 @@
+mod redaction;
+
+pub use redaction::{RedactingMakeWriter, RedactingSpanProcessor, RedactionStrategy, Redactor};
+
+use crate::configuration::TelemetrySettings;
+use actix_web::rt::task::JoinHandle;
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{BatchSpanProcessor, Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tracing::subscriber::set_global_default;
+use tracing::Subscriber;
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+
+/// Compose multiple layers into a `tracing`'s subscriber.
+///
+/// # Implementation Notes
+///
+/// We are using `impl Subscriber` as return type to avoid having to
+/// spell out the actual type of the returned subscriber, which is
+/// indeed quite complex.
+/// We need to explicitly call out that the returned subscriber is
+/// `Send` and `Sync` to make it possible to pass it to `init_subscriber`
+/// later on.
+///
+/// Spans are also handed over to OpenTelemetry when a `tracer_provider`
+/// is given (see `get_tracer_provider`).
+pub fn get_subscriber<Sink>(
+    name: String,
+    env_filter: String,
+    sink: Sink,
+    tracer_provider: Option<&SdkTracerProvider>,
+) -> impl Subscriber + Sync + Send
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let opentelemetry_layer = tracer_provider
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(name.clone())));
+    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    Registry::default()
+        .with(env_filter)
+        .with(JsonStorageLayer)
+        .with(opentelemetry_layer)
+        .with(formatting_layer)
+}
+
+/// Build a tracer provider that exports spans to the OTLP collector
+/// configured in `settings`, if any.
+///
+/// Spans are redacted like logs (see `Redactor`), then exported in batches
+/// from a background thread: call `shutdown` on the provider before exiting
+/// to flush the last ones.
+pub fn get_tracer_provider(
+    settings: &TelemetrySettings,
+) -> Result<Option<SdkTracerProvider>, anyhow::Error> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        // Used as is: the signal path is only appended to endpoints set via env vars
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        settings.sampling_ratio,
+    )));
+    let resource = Resource::builder()
+        .with_service_name(settings.service_name.clone())
+        .build();
+    let processor = BatchSpanProcessor::builder(exporter).build();
+    let builder = if settings.redaction.enabled {
+        let redactor = Redactor::new(&settings.redaction);
+        SdkTracerProvider::builder()
+            .with_span_processor(RedactingSpanProcessor::new(processor, redactor))
+    } else {
+        SdkTracerProvider::builder().with_span_processor(processor)
+    };
+    let provider = builder
+        .with_sampler(sampler)
+        .with_resource(resource)
+        .build();
+    Ok(Some(provider))
+}
+
+/// Register a subscriber as global default to process span data.
+///
+/// It should only be called once!
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    LogTracer::init().expect("Failed to set logger");
+    set_global_default(subscriber).expect("Failed to set subscriber");
+    // W3C `traceparent`: read by `TracingLogger`, written by `trace_context_headers`
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// The trace context of the current span, as headers for an outgoing HTTP
+/// request: whatever the callee records ends up in the same trace.
+///
+/// Empty unless spans are handed over to OpenTelemetry.
+pub fn trace_context_headers() -> HeaderMap {
+    let context = tracing::Span::current().context();
+    let mut headers = HeaderMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+// Just copied trait bounds and signature from `spawn_blocking`
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    actix_web::rt::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{RedactionSettings, TelemetrySettings};
+    use crate::telemetry::{
+        get_subscriber, get_tracer_provider, trace_context_headers, RedactionStrategy,
+    };
+    use actix_web::{web, App, HttpResponse};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_actix_web::TracingLogger;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn settings(otlp_endpoint: String, sampling_ratio: f64) -> TelemetrySettings {
+        TelemetrySettings {
+            service_name: "zero2prod-test".into(),
+            otlp_endpoint: Some(otlp_endpoint),
+            sampling_ratio,
+            redaction: RedactionSettings {
+                enabled: true,
+                strategy: RedactionStrategy::Mask,
+                fields: vec!["email".into()],
+                hash_key: "a-key-for-the-digests-in-our-logs".into(),
+            },
+        }
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    /// Record a span with `provider`, then flush it.
+    async fn record_span(provider: SdkTracerProvider) {
+        let subscriber =
+            get_subscriber("test".into(), "info".into(), std::io::sink, Some(&provider));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "Adding a new subscriber",
+                subscriber_email = "ursula_le_guin@gmail.com"
+            )
+            .in_scope(|| {});
+        });
+        // Waits for the background exporter, which uses a blocking HTTP client
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn no_tracer_provider_without_an_otlp_endpoint() {
+        let settings = TelemetrySettings {
+            otlp_endpoint: None,
+            ..settings(String::new(), 1.0)
+        };
+
+        assert!(get_tracer_provider(&settings).unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_otlp_collector() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&collector)
+            .await;
+        let provider = get_tracer_provider(&settings(collector.uri(), 1.0))
+            .unwrap()
+            .unwrap();
+
+        // Act
+        record_span(provider).await;
+
+        // Assert
+        let request = &collector.received_requests().await.unwrap()[0];
+        // Strings are stored as they are in the protobuf payload
+        assert!(contains(&request.body, "Adding a new subscriber"));
+        assert!(contains(&request.body, "zero2prod-test"));
+        // Redacted like logs
+        assert!(contains(&request.body, "subscriber_email"));
+        assert!(!contains(&request.body, "ursula_le_guin@gmail.com"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nothing_is_exported_with_a_sampling_ratio_of_zero() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&collector)
+            .await;
+        let provider = get_tracer_provider(&settings(collector.uri(), 0.0))
+            .unwrap()
+            .unwrap();
+
+        // Act
+        record_span(provider).await;
+
+        // Assert: the mock verifies on drop
+    }
+
+    #[actix_web::test]
+    async fn the_traceparent_of_incoming_requests_is_propagated_to_outgoing_ones() {
+        // Arrange
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber =
+            get_subscriber("test".into(), "info".into(), std::io::sink, Some(&provider));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = actix_web::test::init_service(App::new().wrap(TracingLogger::default()).route(
+            "/",
+            web::get().to(|| async {
+                let headers = trace_context_headers();
+                HttpResponse::Ok().body(headers["traceparent"].to_str().unwrap().to_owned())
+            }),
+        ))
+        .await;
+        let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+        // Act
+        let request = actix_web::test::TestRequest::get()
+            .uri("/")
+            .insert_header(("traceparent", incoming))
+            .to_request();
+        let outgoing = actix_web::test::call_and_read_body(&app, request).await;
+
+        // Assert
+        let outgoing = std::str::from_utf8(&outgoing).unwrap();
+        // Same trace, but the parent is now our own span
+        assert!(outgoing.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert_ne!(outgoing, incoming);
+    }
+}
@@ -0,0 +1,478 @@
+use crate::configuration::RedactionSettings;
+use hmac::{Hmac, Mac};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Span, SpanData, SpanProcessor};
+use opentelemetry_sdk::Resource;
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// What personal data is replaced with.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionStrategy {
+    /// A fixed `[REDACTED]` placeholder.
+    Mask,
+    /// A short keyed digest: records about the same subscriber can still be
+    /// correlated, without the value itself.
+    Hash,
+}
+
+const MASK: &str = "[REDACTED]";
+
+// Bunyan's own keys: `name` is the application name, not a person's.
+const BUNYAN_KEYS: [&str; 10] = [
+    "v", "name", "msg", "level", "hostname", "pid", "time", "target", "line", "file",
+];
+
+/// Strip personal data from log records and exported spans.
+///
+/// The value of a configured field is redacted whole: `email` matches
+/// `email`, `subscriber_email` and `http.email`. Any other text is searched
+/// for email addresses and `Basic`/`Bearer` credentials.
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    fields: Arc<HashSet<String>>,
+    strategy: RedactionStrategy,
+    hash_key: Arc<str>,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Self {
+        Self {
+            fields: Arc::new(settings.fields.iter().map(|f| f.to_lowercase()).collect()),
+            strategy: settings.strategy,
+            hash_key: settings.hash_key.as_str().into(),
+        }
+    }
+
+    fn is_sensitive(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        // `otel.name`, `otel.kind`, ...: the span's own name and status, not data
+        if key.starts_with("otel.") {
+            return false;
+        }
+        let suffix = key.rsplit(['_', '.', '-']).next().unwrap_or(&key);
+        self.fields.contains(&key) || self.fields.contains(suffix)
+    }
+
+    fn replacement(&self, value: &str) -> String {
+        match self.strategy {
+            RedactionStrategy::Mask => MASK.into(),
+            RedactionStrategy::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(self.hash_key.as_bytes())
+                    .expect("HMAC can take a key of any size.");
+                mac.update(value.as_bytes());
+                format!("hmac:{}", &hex::encode(mac.finalize().into_bytes())[..16])
+            }
+        }
+    }
+
+    /// Redact email addresses and credentials found in free text.
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut ranges: Vec<_> = find_emails(text).chain(find_credentials(text)).collect();
+        ranges.sort_unstable();
+        let mut redacted = String::new();
+        let mut copied_until = 0;
+        for (start, end) in ranges {
+            if start < copied_until {
+                continue;
+            }
+            redacted.push_str(&text[copied_until..start]);
+            redacted.push_str(&self.replacement(&text[start..end]));
+            copied_until = end;
+        }
+        if copied_until == 0 {
+            return Cow::Borrowed(text);
+        }
+        redacted.push_str(&text[copied_until..]);
+        Cow::Owned(redacted)
+    }
+
+    fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => {
+                if let Cow::Owned(redacted) = self.redact_text(s) {
+                    *s = redacted;
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.redact_json(v)),
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    self.redact_json_field(key, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_json_field(&self, key: &str, value: &mut serde_json::Value) {
+        if self.is_sensitive(key) && !value.is_null() {
+            let raw = match &*value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            *value = serde_json::Value::String(self.replacement(&raw));
+        } else {
+            self.redact_json(value);
+        }
+    }
+
+    /// Redact one JSON record, as written by `BunyanFormattingLayer`.
+    fn redact_record(&self, record: &[u8]) -> Vec<u8> {
+        let Ok(serde_json::Value::Object(mut map)) = serde_json::from_slice(record) else {
+            // Not JSON (e.g. a partial write): still look for personal data
+            return self
+                .redact_text(&String::from_utf8_lossy(record))
+                .into_owned()
+                .into_bytes();
+        };
+        for (key, value) in map.iter_mut() {
+            if BUNYAN_KEYS.contains(&key.as_str()) {
+                self.redact_json(value);
+            } else {
+                self.redact_json_field(key, value);
+            }
+        }
+        let mut redacted = serde_json::to_vec(&map).expect("A JSON map can always be serialized.");
+        redacted.push(b'\n');
+        redacted
+    }
+
+    fn redact_attribute(&self, attribute: &mut KeyValue) {
+        if self.is_sensitive(attribute.key.as_str()) {
+            attribute.value = Value::from(self.replacement(&attribute.value.as_str()));
+        } else if let Value::String(s) = &attribute.value {
+            if let Cow::Owned(redacted) = self.redact_text(s.as_str()) {
+                attribute.value = Value::from(redacted);
+            }
+        }
+    }
+}
+
+/// Byte ranges of what looks like an email address.
+fn find_emails(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b".-".contains(&b);
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'@')
+        .filter_map(move |(at, _)| {
+            let start = (0..at).rev().take_while(|i| is_local(bytes[*i])).last()?;
+            let mut end = (at + 1..bytes.len())
+                .take_while(|i| is_domain(bytes[*i]))
+                .last()?
+                + 1;
+            while bytes[end - 1] == b'.' {
+                end -= 1;
+            }
+            text[at + 1..end].contains('.').then_some((start, end))
+        })
+}
+
+/// Byte ranges of the credentials in `Basic ...` or `Bearer ...`.
+fn find_credentials(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let bytes = text.as_bytes();
+    let is_token = |b: u8| b.is_ascii_alphanumeric() || b"-._~+/=".contains(&b);
+    ["Basic ", "Bearer "].into_iter().flat_map(move |scheme| {
+        text.match_indices(scheme).filter_map(move |(i, _)| {
+            let start = i + scheme.len();
+            let end = (start..bytes.len())
+                .take_while(|i| is_token(bytes[*i]))
+                .last()?
+                + 1;
+            Some((start, end))
+        })
+    })
+}
+
+/// Wrap the sink of `BunyanFormattingLayer`: records are redacted before
+/// they are written out.
+pub struct RedactingMakeWriter<M> {
+    inner: M,
+    redactor: Option<Redactor>,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    /// Records go through untouched if redaction is disabled.
+    pub fn new(inner: M, settings: &RedactionSettings) -> Self {
+        Self {
+            inner,
+            redactor: settings.enabled.then(|| Redactor::new(settings)),
+        }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            redactor: self.redactor.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Buffer each line, so that a record is redacted as a whole.
+pub struct RedactingWriter<W: Write> {
+    inner: W,
+    redactor: Option<Redactor>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> RedactingWriter<W> {
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        match &self.redactor {
+            Some(redactor) => self.inner.write_all(&redactor.redact_record(record)),
+            None => self.inner.write_all(record),
+        }
+    }
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let record: Vec<u8> = self.buffer.drain(..=newline).collect();
+            self.write_record(&record)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            let record = std::mem::take(&mut self.buffer);
+            self.write_record(&record)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Redact spans before they are handed over to the exporter.
+#[derive(Debug)]
+pub struct RedactingSpanProcessor<P> {
+    inner: P,
+    redactor: Redactor,
+}
+
+impl<P> RedactingSpanProcessor<P> {
+    pub fn new(inner: P, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for RedactingSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        span.attributes
+            .iter_mut()
+            .for_each(|a| self.redactor.redact_attribute(a));
+        for event in span.events.events.iter_mut() {
+            if let Cow::Owned(name) = self.redactor.redact_text(&event.name) {
+                event.name = Cow::Owned(name);
+            }
+            event
+                .attributes
+                .iter_mut()
+                .for_each(|a| self.redactor.redact_attribute(a));
+        }
+        self.inner.on_end(span)
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.inner.shutdown_with_timeout(timeout)
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::RedactionSettings;
+    use crate::telemetry::redaction::{RedactingMakeWriter, RedactionStrategy, Redactor};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    fn settings(strategy: RedactionStrategy) -> RedactionSettings {
+        RedactionSettings {
+            enabled: true,
+            strategy,
+            fields: vec![
+                "email".into(),
+                "name".into(),
+                "token".into(),
+                "authorization".into(),
+            ],
+            hash_key: "a-key-for-the-digests-in-our-logs".into(),
+        }
+    }
+
+    fn redact(record: serde_json::Value) -> serde_json::Value {
+        let redactor = Redactor::new(&settings(RedactionStrategy::Mask));
+        let redacted = redactor.redact_record(record.to_string().as_bytes());
+        serde_json::from_slice(&redacted).unwrap()
+    }
+
+    #[test]
+    fn configured_fields_are_masked_whatever_their_prefix() {
+        let redacted = redact(serde_json::json!({
+            "name": "zero2prod",
+            "msg": "[ADDING A NEW SUBSCRIBER - START]",
+            "subscriber_email": "ursula_le_guin@gmail.com",
+            "subscriber_name": "le guin",
+            "subscription_token": "abc123",
+            "http.authorization": "Basic dXNlcjpwYXNz",
+            "username": "admin",
+        }));
+
+        assert_eq!(redacted["subscriber_email"], "[REDACTED]");
+        assert_eq!(redacted["subscriber_name"], "[REDACTED]");
+        assert_eq!(redacted["subscription_token"], "[REDACTED]");
+        assert_eq!(redacted["http.authorization"], "[REDACTED]");
+        // Bunyan's own `name` and unrelated fields are left alone
+        assert_eq!(redacted["name"], "zero2prod");
+        assert_eq!(redacted["msg"], "[ADDING A NEW SUBSCRIBER - START]");
+        assert_eq!(redacted["username"], "admin");
+    }
+
+    #[test]
+    fn emails_and_credentials_are_found_in_free_text() {
+        let redacted = redact(serde_json::json!({
+            "msg": "Failed to deliver issue to ursula_le_guin@gmail.com.",
+            "error.cause_chain": "Sent `Authorization: Bearer eyJhbGciOi.eyJzdWIi` to a@b.c",
+        }));
+
+        assert_eq!(redacted["msg"], "Failed to deliver issue to [REDACTED].");
+        assert_eq!(
+            redacted["error.cause_chain"],
+            "Sent `Authorization: Bearer [REDACTED]` to [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn text_without_personal_data_is_left_untouched() {
+        let redactor = Redactor::new(&settings(RedactionStrategy::Mask));
+
+        for text in ["user@localhost", "@gmail.com", "Bearer", "50% off @ 3pm."] {
+            assert_eq!(redactor.redact_text(text), text);
+        }
+    }
+
+    #[test]
+    fn hashing_is_stable_and_hides_the_value() {
+        let redactor = Redactor::new(&settings(RedactionStrategy::Hash));
+
+        let first = redactor
+            .redact_text("ursula_le_guin@gmail.com")
+            .into_owned();
+        let second = redactor
+            .redact_text("ursula_le_guin@gmail.com")
+            .into_owned();
+        let other = redactor.redact_text("ada_lovelace@gmail.com").into_owned();
+
+        assert!(first.starts_with("hmac:"));
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn digests_depend_on_the_hash_key() {
+        let redactor = Redactor::new(&settings(RedactionStrategy::Hash));
+        let other_redactor = Redactor::new(&RedactionSettings {
+            hash_key: "another-key-for-the-digests-in-our-logs".into(),
+            ..settings(RedactionStrategy::Hash)
+        });
+
+        assert_ne!(
+            redactor.redact_text("ursula_le_guin@gmail.com"),
+            other_redactor.redact_text("ursula_le_guin@gmail.com")
+        );
+    }
+
+    #[test]
+    fn span_names_are_not_mistaken_for_personal_names() {
+        let redacted = redact(serde_json::json!({
+            "otel.name": "POST /subscriptions",
+            "otel.kind": "server",
+        }));
+
+        assert_eq!(redacted["otel.name"], "POST /subscriptions");
+        assert_eq!(redacted["otel.kind"], "server");
+    }
+
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_writer_redacts_records_split_across_writes() {
+        // Arrange
+        let sink = Sink::default();
+        let output = sink.0.clone();
+        let make_writer =
+            RedactingMakeWriter::new(move || sink.clone(), &settings(RedactionStrategy::Mask));
+
+        // Act
+        let mut writer = make_writer.make_writer();
+        writer.write_all(br#"{"subscriber_email":"ursula"#).unwrap();
+        writer.write_all(b"_le_guin@gmail.com\"}\n").unwrap();
+
+        // Assert
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "{\"subscriber_email\":\"[REDACTED]\"}\n");
+    }
+
+    #[test]
+    fn records_go_through_untouched_when_redaction_is_disabled() {
+        // Arrange
+        let sink = Sink::default();
+        let output = sink.0.clone();
+        let settings = RedactionSettings {
+            enabled: false,
+            ..settings(RedactionStrategy::Mask)
+        };
+        let make_writer = RedactingMakeWriter::new(move || sink.clone(), &settings);
+        let record = b"{\"subscriber_email\":\"ursula_le_guin@gmail.com\"}\n";
+
+        // Act
+        make_writer.make_writer().write_all(record).unwrap();
+
+        // Assert
+        assert_eq!(output.lock().unwrap().as_slice(), record);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use uuid::Uuid;
+use zero2prod::cli::{add_user, disable_user, get_active_user_id, list_users};
+use zero2prod::domain::NewPassword;
+
+#[tokio::test]
+async fn users_added_from_the_command_line_can_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    add_user(
+        &app.db_pool,
+        &username,
+        None,
+        NewPassword::parse(password.clone()).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &username,
+            "password": &password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn list_users_returns_every_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    add_user(
+        &app.db_pool,
+        &username,
+        None,
+        NewPassword::parse(Uuid::new_v4().to_string()).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    // Act
+    let users = list_users(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(users.len(), 2);
+    assert!(users.iter().any(|u| u.username == username));
+    assert!(users.iter().any(|u| u.username == app.test_user.username));
+    assert!(users.iter().all(|u| u.disabled_at.is_none()));
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_log_in_and_lose_their_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login().await;
+
+    // Act
+    disable_user(&app.db_pool, &app.test_user.username)
+        .await
+        .unwrap();
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(get_active_user_id(&app.db_pool, &app.test_user.username)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn disabling_an_unknown_user_fails() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let outcome = disable_user(&app.db_pool, "nobody").await;
+
+    // Assert
+    assert!(outcome.is_err());
+}
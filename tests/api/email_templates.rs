@@ -0,0 +1,125 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
+#[tokio::test]
+async fn the_application_refuses_to_start_with_broken_templates() {
+    // Arrange
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&directory).unwrap();
+    for entry in std::fs::read_dir("templates").unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, directory.join(path.file_name().unwrap())).unwrap();
+    }
+    std::fs::write(directory.join("newsletter.html"), "{% block %}").unwrap();
+    let mut configuration = get_configuration().unwrap();
+    configuration.application.port = 0;
+    configuration.email_templates.directory = directory.to_string_lossy().into_owned();
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    assert!(outcome.is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn confirmation_emails_greet_the_subscriber_by_name() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Welcome to our newsletter, le guin!"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Welcome to our newsletter, le guin!"));
+}
+
+#[tokio::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Dear {{ subscriber.name }}, {{ unsubscribe_url }}",
+                "html": "<p>Dear {{ subscriber.name }}</p>",
+            }
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(html.contains("<p>Dear le guin</p>"));
+    assert!(text.starts_with("Dear le guin, http://127.0.0.1/subscriptions/unsubscribe?"));
+}
+
+#[tokio::test]
+async fn newsletters_that_are_not_valid_templates_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("{% if %}", "a syntax error"),
+        ("{{ subscriber.age }}", "an unknown variable"),
+    ];
+
+    for (html, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": html,
+                }
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the content had {}.",
+            description
+        );
+    }
+}
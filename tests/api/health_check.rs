@@ -1,4 +1,8 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
+use sqlx::{Connection, Executor, PgConnection};
+use wiremock::matchers::{header_exists, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
 
 // `actix_rt::test` is the testing equivalent of `actix_web::main`.
 // It also spares you from having to specify the `#[test]` attribute.
@@ -28,3 +32,124 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn ready_returns_200_when_every_dependency_is_up() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert!(body["checks"]["database"]["latency_ms"].is_u64());
+    assert_eq!(body["checks"]["migrations"]["status"], "up");
+    // Not checked unless asked to
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn ready_returns_503_if_migrations_are_pending() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+}
+
+#[tokio::test]
+async fn ready_returns_503_if_the_database_is_unreachable() {
+    // Arrange
+    let app = spawn_app().await;
+    let database_name: String = sqlx::query_scalar!(r#"SELECT current_database() AS "name!""#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    // Cut every connection, including the ones in the application's pool
+    let configuration = get_configuration().unwrap();
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap();
+    connection
+        .execute(
+            format!(
+                r#"ALTER DATABASE "{}" ALLOW_CONNECTIONS false"#,
+                database_name
+            )
+            .as_str(),
+        )
+        .await
+        .unwrap();
+    sqlx::query!(
+        "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1",
+        database_name
+    )
+    .fetch_all(&mut connection)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "down");
+}
+
+#[tokio::test]
+async fn ready_pings_the_email_provider_when_enabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.health.check_email_provider = true).await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .and(header_exists("X-Postmark-Server-Token"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["status"], "up");
+}
+
+#[tokio::test]
+async fn ready_returns_503_if_the_email_provider_is_down() {
+    // Arrange
+    let app = spawn_app_with(|c| c.health.check_email_provider = true).await;
+    Mock::given(path("/server"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["email_provider"]["status"], "down");
+}
@@ -3,16 +3,23 @@ use once_cell::sync::Lazy;
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::startup::{get_connection_pool, Application};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_templates::EmailTemplates;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::migrations::MIGRATOR;
+use zero2prod::startup::{get_connection_pool, Application, ApplicationBaseUrl, HmacSecret};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
 }
 
 impl TestUser {
@@ -21,6 +28,7 @@ impl TestUser {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            email: format!("{}@example.com", Uuid::new_v4()),
         }
     }
 
@@ -39,11 +47,12 @@ impl TestUser {
         // let password_hash = sha3::Sha3_256::digest(self.password.as_bytes());
         // let password_hash = format!("{:x}", password_hash);
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)\
-            VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, email)\
+            VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.email,
         )
         .execute(pool)
         .await
@@ -57,14 +66,52 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub port: u16,
     pub test_user: TestUser,
+    pub email_client: Arc<EmailClient>,
+    pub email_templates: EmailTemplates,
+    pub base_url: String,
+    pub hmac_secret: String,
+    // Keeps cookies and does not follow redirects
+    pub api_client: reqwest::Client,
 }
 
 impl TestApp {
+    /// Drain `issue_delivery_queue` so that newsletter emails are sent
+    /// before the test makes its assertions.
+    pub async fn dispatch_all_pending_emails(&self) {
+        let base_url = ApplicationBaseUrl(self.base_url.clone());
+        let hmac_secret = HmacSecret(self.hmac_secret.clone());
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.email_templates,
+                &base_url,
+                &hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                // The background worker might still be holding a task:
+                // wait for it to be deleted before returning.
+                let pending = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+                    .fetch_one(&self.db_pool)
+                    .await
+                    .unwrap()
+                    .count
+                    .unwrap_or(0);
+                if pending == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         // let (username, password) = self.test_user().await;
 
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             // Random credentials!
             // `reqwest` does all the encoding/formatting heavy-lifting for us.
             // No longer randomly generated on the spot!
@@ -75,9 +122,154 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_scheduled_issues(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_scheduled_issue(
+        &self,
+        issue_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/newsletters/scheduled/{}",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_scheduled_issue(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletters/scheduled/{}",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.draft_request(reqwest::Method::POST, "")
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_draft(&self, draft_id: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.draft_request(reqwest::Method::PUT, &format!("/{}", draft_id))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_draft(&self, draft_id: &str) -> reqwest::Response {
+        self.draft_request(reqwest::Method::GET, &format!("/{}", draft_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_draft_preview(&self, draft_id: &str) -> reqwest::Response {
+        self.draft_request(reqwest::Method::GET, &format!("/{}/preview", draft_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft_action(
+        &self,
+        draft_id: &str,
+        action: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.draft_request(reqwest::Method::POST, &format!("/{}/{}", draft_id, action))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    fn draft_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(
+                method,
+                format!("{}/newsletters/drafts{}", &self.address, path),
+            )
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn get_delivery_report(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/{}/report", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_retry_failed(&self, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/{}/retry-failed",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_form_token(&self) -> String {
+        let body: serde_json::Value = self
+            .api_client
+            .get(format!("{}/subscriptions/form-token", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap();
+        body["form_token"].as_str().unwrap().to_owned()
+    }
+
+    /// Submit the subscription form, along with a fresh form token.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let body = format!("{}&form_token={}", body, self.get_form_token().await);
+        self.post_subscriptions_without_form_token(body).await
+    }
+
+    pub async fn post_subscriptions_without_form_token(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -85,6 +277,25 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_health_ready(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        self.api_client
+            .get(format!("{}/metrics", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -103,11 +314,154 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Log in as the test user.
+    pub async fn login(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await;
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_password_reset_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password-reset", &self.address))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_password_reset_request_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/password-reset", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_password_reset<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password-reset/confirm", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// The value of a header of an email sent through Postmark.
+    pub fn get_email_header(
+        &self,
+        email_request: &wiremock::Request,
+        name: &str,
+    ) -> Option<String> {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        body["Headers"]
+            .as_array()?
+            .iter()
+            .find(|h| h["Name"] == name)
+            .map(|h| h["Value"].as_str().unwrap().to_owned())
+    }
+
+    /// Extract the one-click unsubscribe link from the `List-Unsubscribe`
+    /// header of a newsletter email sent through Postmark.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("The email has no `List-Unsubscribe` header.");
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     // pub async fn test_user(&self) -> (String, String) {
     //     let row = sqlx::query!("SELECT username, password FROM users LIMIT 1",)
     //         .fetch_one(&self.db_pool)
@@ -118,6 +472,11 @@ impl TestApp {
     // }
 }
 
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
@@ -129,15 +488,21 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     let subscriber_name = "test".to_string();
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::stdout, None);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink, None);
         init_subscriber(subscriber);
     }
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a chance to tweak the settings first.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -153,6 +518,9 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Tests fill in the subscription form faster than any human
+        c.bot_protection.min_submit_seconds = 0;
+        configure(&mut c);
         c
     };
 
@@ -170,14 +538,23 @@ pub async fn spawn_app() -> TestApp {
     // let address = format!("http://127.0.0.1:{}", application_port);
 
     // background task
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let test_app = TestApp {
         address: format!("http://127.0.0.1:{}", application_port),
-        db_pool: get_connection_pool(&configuration.database),
+        db_pool: get_connection_pool(&configuration),
         email_server,
         port: application_port,
         test_user: TestUser::generate(),
+        email_client: configuration.email_client.client(),
+        email_templates: configuration.email_templates.templates().unwrap(),
+        base_url: configuration.application.base_url.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -241,7 +618,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to connect to Postgres.");
 
-    sqlx::migrate!("./migrations")
+    MIGRATOR
         .run(&connection_pool)
         .await
         .expect("Failed to migrate the database");
@@ -261,3 +638,43 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 //         .await
 //         .expect("Failed to create test users.");
 // }
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // We are not using `mount`!
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // We now inspect the requests received by the mock Postmark server
+    // to retrieve the confirmation link and return it
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    // We can then reuse the same helper and just add
+    // an extra step to actually call the confirmation link!
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -1,5 +1,20 @@
+mod admin_dashboard;
+mod change_password;
+mod cli;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod login;
+mod metrics;
+mod migrations;
 mod newsletters;
+mod newsletters_drafts;
+mod newsletters_report;
+mod newsletters_scheduled;
+mod password_reset;
+mod request_id;
 mod subscriptions;
+mod subscriptions_bot_protection;
 mod subscriptions_confirm;
+mod subscriptions_rate_limit;
+mod subscriptions_unsubscribe;
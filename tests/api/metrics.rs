@@ -0,0 +1,137 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Find the value of a sample in the Prometheus text format, e.g.
+/// `zero2prod_emails_sent_total`.
+fn sample(metrics: &str, name: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn metrics_are_exposed_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let body = response.text().await.unwrap();
+    assert!(sample(&body, "zero2prod_db_pool_connections").is_some());
+    assert!(sample(&body, "zero2prod_db_pool_idle_connections").is_some());
+    assert!(sample(&body, "zero2prod_db_pool_acquire_wait_seconds").is_some());
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_route_pattern_and_status() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    for _ in 0..2 {
+        reqwest::get(&format!("{}/health_check", &app.address))
+            .await
+            .unwrap();
+    }
+    // Path parameters and unknown paths must not blow up the label set
+    reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=abc",
+        &app.address
+    ))
+    .await
+    .unwrap();
+    reqwest::get(&format!("{}/does-not-exist", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    let metrics = app.get_metrics().await;
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"zero2prod_http_requests_total{method="GET",route="/health_check",status="200"}"#
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"zero2prod_http_requests_total{method="GET",route="/subscriptions/confirm",status="401"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"zero2prod_http_requests_total{method="GET",route="unmatched",status="404"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"zero2prod_http_request_duration_seconds_count{method="GET",route="/health_check"}"#
+        ),
+        Some(2.0)
+    );
+}
+
+#[tokio::test]
+async fn subscriptions_created_and_confirmed_are_counted() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    create_confirmed_subscriber(&app).await;
+
+    // Assert
+    let metrics = app.get_metrics().await;
+    assert_eq!(
+        sample(&metrics, "zero2prod_subscriptions_created_total"),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, "zero2prod_subscriptions_confirmed_total"),
+        Some(1.0)
+    );
+    assert_eq!(sample(&metrics, "zero2prod_emails_sent_total"), Some(1.0));
+}
+
+#[tokio::test]
+async fn failed_emails_are_counted_by_error_class() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let metrics = app.get_metrics().await;
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"zero2prod_emails_failed_total{error_class="permanent"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(sample(&metrics, "zero2prod_emails_sent_total"), Some(0.0));
+}
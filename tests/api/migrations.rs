@@ -0,0 +1,99 @@
+use crate::helpers::spawn_app;
+use sqlx::{Connection, Executor, PgConnection};
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::migrations::{migration_status, MIGRATOR};
+use zero2prod::startup::{get_connection_pool, Application};
+
+/// Settings pointing at a brand new database, without any migration applied.
+async fn empty_database() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.application.port = 0;
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(
+            format!(
+                r#"CREATE DATABASE "{}";"#,
+                configuration.database.database_name
+            )
+            .as_str(),
+        )
+        .await
+        .expect("Failed to create database.");
+    configuration
+}
+
+#[tokio::test]
+async fn migrate_on_startup_applies_every_pending_migration() {
+    // Arrange
+    let mut configuration = empty_database().await;
+    configuration.database.migrate_on_startup = true;
+    let pool = get_connection_pool(&configuration);
+    let before = migration_status(&pool).await.unwrap();
+    assert!(before.applied.is_empty());
+    assert_eq!(before.pending.len(), MIGRATOR.iter().count());
+
+    // Act
+    Application::build(configuration)
+        .await
+        .expect("Failed to build application.");
+
+    // Assert
+    let after = migration_status(&pool).await.unwrap();
+    assert!(after.pending.is_empty());
+    assert_eq!(after.applied.len(), MIGRATOR.iter().count());
+}
+
+#[tokio::test]
+async fn pending_migrations_are_left_alone_unless_asked_to() {
+    // Arrange
+    let mut configuration = empty_database().await;
+    configuration.database.migrate_on_startup = false;
+    let pool = get_connection_pool(&configuration);
+
+    // Act
+    Application::build(configuration)
+        .await
+        .expect("Failed to build application.");
+
+    // Assert
+    let status = migration_status(&pool).await.unwrap();
+    assert!(status.applied.is_empty());
+}
+
+#[tokio::test]
+async fn the_application_refuses_to_start_on_a_newer_database() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99990101000000, 'from the future', true, '\x00', 0)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let database_name: String = sqlx::query_scalar!(r#"SELECT current_database() AS "name!""#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    for migrate_on_startup in [false, true] {
+        let mut configuration = get_configuration().unwrap();
+        configuration.database.database_name = database_name.clone();
+        configuration.database.migrate_on_startup = migrate_on_startup;
+        configuration.application.port = 0;
+
+        // Act
+        let outcome = Application::build(configuration).await;
+
+        // Assert
+        assert!(outcome.is_err());
+    }
+    let status = migration_status(&app.db_pool).await.unwrap();
+    assert_eq!(status.unknown, vec![99990101000000]);
+}
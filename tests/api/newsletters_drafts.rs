@@ -0,0 +1,301 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Create a draft and return its id.
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_draft(&draft("Newsletter title")).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["version"], 1);
+    body["draft_id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn drafts_are_not_sent_to_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    create_draft(&app).await;
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn every_edit_creates_a_new_version() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+    let mut edit = draft("A better title");
+    edit["version"] = 1.into();
+
+    // Act
+    let response = app.put_draft(&draft_id, &edit).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["version"], 2);
+    let latest: serde_json::Value = app.get_draft(&draft_id).await.json().await.unwrap();
+    assert_eq!(latest["title"], "A better title");
+    let first: serde_json::Value = reqwest::Client::new()
+        .get(format!(
+            "{}/newsletters/drafts/{}?version=1",
+            &app.address, draft_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(first["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn drafts_written_in_markdown_keep_their_source() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"markdown": "Newsletter body as *markdown*"}
+    });
+
+    // Act
+    let response = app.post_draft(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let draft: serde_json::Value = app
+        .get_draft(created["draft_id"].as_str().unwrap())
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        draft["content"]["markdown"],
+        "Newsletter body as *markdown*"
+    );
+    assert_eq!(
+        draft["content"]["html"],
+        "<p>Newsletter body as <em>markdown</em></p>\n"
+    );
+    assert_eq!(draft["content"]["text"], "Newsletter body as markdown");
+}
+
+#[tokio::test]
+async fn edits_based_on_a_stale_version_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+    let mut edit = draft("A better title");
+    edit["version"] = 1.into();
+    app.put_draft(&draft_id, &edit).await;
+
+    // Act
+    let mut stale_edit = draft("A competing title");
+    stale_edit["version"] = 1.into();
+    let response = app.put_draft(&draft_id, &stale_edit).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/draft-conflict");
+}
+
+#[tokio::test]
+async fn the_preview_renders_the_html_subscribers_receive() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+
+    // Act
+    let response = app.get_draft_preview(&draft_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let html = response.text().await.unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?subscriber_id="));
+}
+
+#[tokio::test]
+async fn unknown_drafts_return_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_draft_preview(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/unknown-draft");
+}
+
+#[tokio::test]
+async fn drafts_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/drafts", &app.address))
+        .json(&draft("Newsletter title"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_test_send_only_reaches_the_given_recipients() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_draft_action(
+            &draft_id,
+            "test-send",
+            &serde_json::json!({"recipients": ["editor@example.com"]}),
+        )
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Newsletter title");
+}
+
+#[tokio::test]
+async fn a_test_send_to_invalid_recipients_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+    let test_cases = vec![
+        (serde_json::json!([]), "no recipients"),
+        (serde_json::json!(["not-an-email"]), "an invalid address"),
+        (
+            serde_json::json!(vec!["editor@example.com"; 11]),
+            "too many recipients",
+        ),
+    ];
+
+    for (recipients, description) in test_cases {
+        // Act
+        let response = app
+            .post_draft_action(
+                &draft_id,
+                "test-send",
+                &serde_json::json!({ "recipients": recipients }),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_draft_action(&draft_id, "publish", &serde_json::json!({"version": 1}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = app.get_draft(&draft_id).await.json().await.unwrap();
+    assert!(published["newsletter_issue_id"].is_string());
+    // Published drafts can be neither edited nor published again
+    let response = app
+        .post_draft_action(&draft_id, "publish", &serde_json::json!({"version": 1}))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+    let mut edit = draft("A better title");
+    edit["version"] = 1.into();
+    assert_eq!(app.put_draft(&draft_id, &edit).await.status().as_u16(), 409);
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn only_the_latest_version_can_be_published() {
+    // Arrange
+    let app = spawn_app().await;
+    let draft_id = create_draft(&app).await;
+    let mut edit = draft("A better title");
+    edit["version"] = 1.into();
+    app.put_draft(&draft_id, &edit).await;
+
+    // Act
+    let response = app
+        .post_draft_action(&draft_id, "publish", &serde_json::json!({"version": 1}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
@@ -0,0 +1,233 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, body_partial_json, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const FAILING_EMAIL: &str = "unlucky@example.com";
+
+fn newsletter() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Publish an issue, deliver it and return its id.
+async fn publish_and_deliver(app: &TestApp) -> String {
+    let response = app.post_newsletters(newsletter()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    app.dispatch_all_pending_emails().await;
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// A second confirmed subscriber, straight into the database.
+async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+        VALUES ($1, $2, 'unlucky', now(), 'confirmed')",
+        Uuid::new_v4(),
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn the_report_counts_sent_deliveries_and_keeps_the_message_id() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_and_deliver(&app).await;
+    let response = app.get_delivery_report(&issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        report["deliveries"],
+        serde_json::json!({"queued": 0, "sent": 1, "failed": 0, "bounced": 0, "skipped": 0})
+    );
+    assert_eq!(report["failed_recipients"], serde_json::json!([]));
+    let delivery = sqlx::query!(
+        "SELECT attempts, provider_message_id FROM newsletter_deliveries \
+        WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(&issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.attempts, 1);
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+}
+
+#[tokio::test]
+async fn failed_recipients_are_listed_in_the_report() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber_with_email(&app, FAILING_EMAIL).await;
+    Mock::given(body_partial_json(serde_json::json!({"To": FAILING_EMAIL})))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let issue_id = publish_and_deliver(&app).await;
+    let report: serde_json::Value = app
+        .get_delivery_report(&issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(report["deliveries"]["sent"], 1);
+    assert_eq!(report["deliveries"]["failed"], 1);
+    let failed = &report["failed_recipients"][0];
+    assert_eq!(failed["email"], FAILING_EMAIL);
+    assert_eq!(failed["attempts"], 1);
+    assert!(failed["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("The email provider rejected the request"));
+}
+
+#[tokio::test]
+async fn recipients_who_unsubscribe_before_delivery_are_reported_as_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter()).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    // Act
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let report: serde_json::Value = app
+        .get_delivery_report(issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        report["deliveries"],
+        serde_json::json!({"queued": 0, "sent": 0, "failed": 0, "bounced": 0, "skipped": 1})
+    );
+}
+
+#[tokio::test]
+async fn retrying_only_resends_to_failed_recipients() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber_with_email(&app, FAILING_EMAIL).await;
+    let issue_id = {
+        let _failing = Mock::given(body_partial_json(serde_json::json!({"To": FAILING_EMAIL})))
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let _succeeding = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        publish_and_deliver(&app).await
+    };
+    Mock::given(body_partial_json(serde_json::json!({"To": FAILING_EMAIL})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_retry_failed(&issue_id).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["retried"], 1);
+    let report: serde_json::Value = app
+        .get_delivery_report(&issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        report["deliveries"],
+        serde_json::json!({"queued": 0, "sent": 2, "failed": 0, "bounced": 0, "skipped": 0})
+    );
+    // Mock verifies on Drop that the failed recipient got the issue on the retry
+}
+
+#[tokio::test]
+async fn the_report_of_an_unknown_issue_returns_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_delivery_report(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/unknown-issue");
+}
+
+#[tokio::test]
+async fn the_report_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/newsletters/{}/report",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
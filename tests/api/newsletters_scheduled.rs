@@ -0,0 +1,233 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::newsletter_scheduler::dispatch_due_issues;
+
+fn scheduled_newsletter(send_at: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "send_at": send_at,
+    })
+}
+
+fn in_one_day() -> String {
+    (Utc::now() + Duration::days(1)).to_rfc3339()
+}
+
+/// Schedule an issue for tomorrow and return its id.
+async fn schedule_issue(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(scheduled_newsletter(&in_one_day()))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+/// Pretend the issue's `send_at` has passed.
+async fn make_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() - interval '1 minute' \
+        WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_only_delivered_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app).await;
+
+    // Nothing goes out before `send_at`
+    {
+        let _mock_guard = Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        assert_eq!(dispatch_due_issues(&app.db_pool).await.unwrap(), 0);
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // Act
+    make_due(&app, &issue_id).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    assert_eq!(dispatch_due_issues(&app.db_pool).await.unwrap(), 1);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert_eq!(scheduled, serde_json::json!([]));
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn send_at_must_have_an_explicit_timezone_and_be_in_the_future() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("2030-01-01T09:00:00", "no timezone"),
+        ("next tuesday", "not a timestamp"),
+        ("2001-01-01T09:00:00+01:00", "in the past"),
+    ];
+
+    for (send_at, description) in test_cases {
+        // Act
+        let response = app.post_newsletters(scheduled_newsletter(send_at)).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when `send_at` was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_listed_in_utc() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(scheduled_newsletter("2030-01-01T09:00:00+01:00"))
+        .await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+
+    // Act
+    let response = app.get_scheduled_issues().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let scheduled: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        scheduled,
+        serde_json::json!([{
+            "newsletter_issue_id": issue["newsletter_issue_id"],
+            "title": "Newsletter title",
+            "send_at": "2030-01-01T08:00:00+00:00",
+        }])
+    );
+}
+
+#[tokio::test]
+async fn listing_scheduled_issues_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/newsletters/scheduled", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = schedule_issue(&app).await;
+
+    // Act
+    let response = app
+        .put_scheduled_issue(
+            &issue_id,
+            &serde_json::json!({"send_at": "2031-06-01T12:30:00Z"}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert_eq!(scheduled[0]["send_at"], "2031-06-01T12:30:00+00:00");
+}
+
+#[tokio::test]
+async fn rescheduling_an_unknown_issue_returns_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .put_scheduled_issue(
+            &Uuid::new_v4().to_string(),
+            &serde_json::json!({"send_at": in_one_day()}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/unknown-scheduled-issue");
+}
+
+#[tokio::test]
+async fn a_cancelled_issue_is_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.delete_scheduled_issue(&issue_id).await;
+    make_due(&app, &issue_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(dispatch_due_issues(&app.db_pool).await.unwrap(), 0);
+    app.dispatch_all_pending_emails().await;
+    // Cancelling twice: the issue is no longer scheduled
+    let response = app.delete_scheduled_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_dispatch_an_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app).await;
+    make_due(&app, &issue_id).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (first, second) = tokio::join!(
+        dispatch_due_issues(&app.db_pool),
+        dispatch_due_issues(&app.db_pool)
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(first.unwrap() + second.unwrap(), 1);
+    // Mock verifies on Drop that the newsletter email went out once
+}
@@ -0,0 +1,204 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const ACKNOWLEDGEMENT: &str = "you will shortly receive a link to reset your password";
+
+/// Ask for a reset link for the test user and return the token it carries.
+async fn request_reset_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_password_reset_request(&app.test_user.email).await;
+    assert_is_redirect_to(&response, "/password-reset");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(&email_request);
+    assert_eq!(links.html.path(), "/password-reset/confirm");
+    links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "reset_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn unknown_emails_get_the_same_answer_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for a reset link
+    let response = app
+        .post_password_reset_request("someone-else@example.com")
+        .await;
+    assert_is_redirect_to(&response, "/password-reset");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_password_reset_request_html().await;
+    assert!(html_page.contains(ACKNOWLEDGEMENT));
+    // Mock verifies on Drop that we haven't sent an email
+}
+
+#[tokio::test]
+async fn the_reset_link_lets_the_user_choose_a_new_password() {
+    // Arrange
+    let app = spawn_app().await;
+    let reset_token = request_reset_token(&app).await;
+    let html_page = app.get_password_reset_request_html().await;
+    assert!(html_page.contains(ACKNOWLEDGEMENT));
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Open the link
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/password-reset/confirm?reset_token={}",
+            app.address, reset_token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Choose a new password
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Login using the new password
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn resetting_the_password_logs_the_user_out_everywhere() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let reset_token = request_reset_token(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_reset_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let reset_token = request_reset_token(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "reset_token": &reset_token,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    let response = app.post_password_reset(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    let response = app.post_password_reset(&body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password-reset");
+    let html_page = app.get_password_reset_request_html().await;
+    assert!(html_page.contains("The password reset link is invalid or has expired."));
+}
+
+#[tokio::test]
+async fn an_expired_reset_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let reset_token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password-reset");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_new_password_must_have_a_valid_length() {
+    // Arrange
+    let app = spawn_app().await;
+    let reset_token = request_reset_token(&app).await;
+
+    // Act
+    let response = app
+        .post_password_reset(&serde_json::json!({
+            "reset_token": &reset_token,
+            "new_password": "too-short",
+            "new_password_check": "too-short",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/password-reset/confirm?reset_token={}", reset_token),
+    );
+}
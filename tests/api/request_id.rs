@@ -0,0 +1,179 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_none_is_provided() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn an_incoming_request_id_is_echoed_back() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "proxy-assigned.42")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.headers()["X-Request-Id"], "proxy-assigned.42");
+}
+
+#[tokio::test]
+async fn redirects_raised_by_middlewares_carry_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Anonymous users are turned away before reaching the handler
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .header("X-Request-Id", "proxy-assigned.42")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers()["Location"], "/login");
+    assert_eq!(response.headers()["X-Request-Id"], "proxy-assigned.42");
+}
+
+#[tokio::test]
+async fn an_invalid_incoming_request_id_is_replaced() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", "not a valid id")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let request_id = response.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn problem_details_quote_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "support-ticket-1234")
+        .body("name=Ursula&email=definitely-not-an-email")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["X-Request-Id"], "support-ticket-1234");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["request_id"], "support-ticket-1234");
+}
+
+#[tokio::test]
+async fn the_confirmation_email_carries_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "signup-from-proxy")
+        .body(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            app.get_form_token().await
+        ))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(
+        app.get_email_header(email_request, "X-Request-Id")
+            .as_deref(),
+        Some("signup-from-proxy")
+    );
+}
+
+#[tokio::test]
+async fn newsletter_deliveries_carry_the_request_id_that_published_the_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-Request-Id", "publish-issue-7")
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(
+        app.get_email_header(&email_request, "X-Request-Id")
+            .as_deref(),
+        Some("publish-issue-7")
+    );
+}
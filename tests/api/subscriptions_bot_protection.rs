@@ -0,0 +1,148 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::bot_protection::FakeChallengeVerifier;
+use zero2prod::configuration::ChallengeVerifierKind;
+
+const VALID_FORM: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+/// Bots must not cost us a single email.
+async fn expect_no_email(app: &TestApp) {
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn assert_rejected(app: &TestApp, response: reqwest::Response, detail: &str) {
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    assert!(
+        problem["detail"].as_str().unwrap().contains(detail),
+        "Unexpected detail: {}",
+        problem["detail"]
+    );
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn form_tokens_are_not_cached() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/subscriptions/form-token", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["form_token"].is_string());
+}
+
+#[tokio::test]
+async fn subscriptions_without_a_form_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_no_email(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions_without_form_token(VALID_FORM.into())
+        .await;
+
+    // Assert
+    assert_rejected(&app, response, "form token is missing").await;
+}
+
+#[tokio::test]
+async fn subscriptions_with_a_forged_form_token_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_no_email(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions_without_form_token(format!(
+            "{}&form_token=1700000000.abcdef",
+            VALID_FORM
+        ))
+        .await;
+
+    // Assert
+    assert_rejected(&app, response, "form token is invalid").await;
+}
+
+#[tokio::test]
+async fn subscriptions_with_a_filled_in_honeypot_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_no_email(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "{}&website=https%3A%2F%2Fspam.example.com",
+            VALID_FORM
+        ))
+        .await;
+
+    // Assert
+    assert_rejected(&app, response, "not filled in by a human").await;
+}
+
+#[tokio::test]
+async fn subscriptions_submitted_too_quickly_are_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| c.bot_protection.min_submit_seconds = 60).await;
+    expect_no_email(&app).await;
+
+    // Act
+    let response = app.post_subscriptions(VALID_FORM.into()).await;
+
+    // Assert
+    assert_rejected(&app, response, "submitted too quickly").await;
+}
+
+#[tokio::test]
+async fn subscriptions_must_solve_the_challenge_when_one_is_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge_verifier = ChallengeVerifierKind::Fake;
+    })
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let unsolved = app
+        .post_subscriptions(format!("{}&challenge_response=beep-boop", VALID_FORM))
+        .await;
+    // Assert
+    assert_rejected(&app, unsolved, "challenge was not solved").await;
+
+    // Act
+    let solved = app
+        .post_subscriptions(format!(
+            "{}&challenge_response={}",
+            VALID_FORM,
+            FakeChallengeVerifier::PASSING_RESPONSE
+        ))
+        .await;
+    // Assert
+    assert_eq!(solved.status().as_u16(), 200);
+}
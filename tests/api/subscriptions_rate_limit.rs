@@ -0,0 +1,206 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::RateLimitStoreKind;
+use zero2prod::rate_limit::{delete_full_buckets, TokenBucket};
+
+fn bucket(capacity: u32) -> TokenBucket {
+    TokenBucket {
+        capacity,
+        refill_interval_seconds: 3600,
+    }
+}
+
+async fn post_subscriptions_from(
+    app: &TestApp,
+    email: &str,
+    forwarded_for: Option<&str>,
+) -> reqwest::Response {
+    let form_token = app.get_form_token().await;
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .form(&[
+            ("name", "le guin"),
+            ("email", email),
+            ("form_token", &form_token),
+        ]);
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn repeated_subscriptions_for_the_same_email_are_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_email = bucket(2)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..2 {
+        post_subscriptions_from(&app, "ursula_le_guin@gmail.com", None)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    // Case and surrounding spaces do not make for a different address
+    let response = post_subscriptions_from(&app, " Ursula_Le_Guin@gmail.com", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/rate-limited");
+    // Mock verifies on Drop that only two confirmation emails went out
+}
+
+#[tokio::test]
+async fn too_many_subscriptions_from_the_same_client_are_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = bucket(2)).await;
+    accept_all_emails(&app).await;
+
+    // Act
+    for email in ["a@example.com", "b@example.com"] {
+        post_subscriptions_from(&app, email, None)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    let response = post_subscriptions_from(&app, "c@example.com", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn forwarded_for_is_ignored_unless_the_peer_is_a_trusted_proxy() {
+    // Arrange
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = bucket(1)).await;
+    accept_all_emails(&app).await;
+
+    // Act
+    let first = post_subscriptions_from(&app, "a@example.com", Some("198.51.100.1")).await;
+    let second = post_subscriptions_from(&app, "b@example.com", Some("198.51.100.2")).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_limited_separately() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip = bucket(1);
+        c.rate_limit.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    })
+    .await;
+    accept_all_emails(&app).await;
+
+    // Act
+    let first = post_subscriptions_from(&app, "a@example.com", Some("198.51.100.1")).await;
+    let other_client = post_subscriptions_from(&app, "b@example.com", Some("198.51.100.2")).await;
+    let first_again = post_subscriptions_from(&app, "c@example.com", Some("198.51.100.1")).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(other_client.status().as_u16(), 200);
+    assert_eq!(first_again.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_in_memory_store_enforces_the_same_limits() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.store = RateLimitStoreKind::Memory;
+        c.rate_limit.per_email = bucket(1);
+    })
+    .await;
+    accept_all_emails(&app).await;
+
+    // Act
+    let first = post_subscriptions_from(&app, "ursula_le_guin@gmail.com", None).await;
+    let second = post_subscriptions_from(&app, "ursula_le_guin@gmail.com", None).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    let stored = sqlx::query!("SELECT COUNT(*) AS count FROM rate_limit_buckets")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, Some(0));
+}
+
+#[tokio::test]
+async fn rate_limit_buckets_do_not_store_email_or_ip_addresses() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+    accept_all_emails(&app).await;
+
+    // Act
+    post_subscriptions_from(&app, "ursula_le_guin@gmail.com", None)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let keys = sqlx::query!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys.len(), 2);
+    assert!(keys.iter().all(|r| !r.key.contains("ursula")));
+    assert!(keys.iter().all(|r| !r.key.contains("127.0.0.1")));
+}
+
+#[tokio::test]
+async fn refilled_rate_limit_buckets_are_deleted() {
+    // Arrange
+    let app = spawn_app_with(|_| {}).await;
+    accept_all_emails(&app).await;
+    post_subscriptions_from(&app, "ursula_le_guin@gmail.com", None)
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(delete_full_buckets(&app.db_pool).await.unwrap(), 0);
+
+    // Act - The per-IP bucket has been refilled in the meantime
+    sqlx::query!(
+        "UPDATE rate_limit_buckets SET full_at = now() - interval '1 second' \
+        WHERE key LIKE 'subscriptions:ip:%'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let n_deleted = delete_full_buckets(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_deleted, 1);
+    let keys = sqlx::query!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(keys[0].key.starts_with("subscriptions:email:"));
+}